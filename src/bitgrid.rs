@@ -3,41 +3,6 @@ use std::simd::{LaneCount, Simd, SimdElement, SupportedLaneCount};
 
 use num::PrimInt;
 
-struct Quad<T> {
-    nw: T,
-    ne: T,
-    sw: T,
-    se: T,
-}
-
-impl<T> Quad<T> {
-    fn to_array(self) -> [T; 4] {
-        [self.nw, self.ne, self.sw, self.se]
-    }
-
-    fn from_array([nw, ne, sw, se]: [T; 4]) -> Self {
-        Self { nw, ne, sw, se }
-    }
-
-    fn map<U>(self, f: impl FnMut(T) -> U) -> Quad<U> {
-        self.to_array().map(f).to_quad()
-    }
-
-    fn try_map<U>(self, f: impl FnMut(T) -> Option<U>) -> Option<Quad<U>> {
-        Some(self.to_array().try_map(f)?.to_quad())
-    }
-}
-
-trait ArrayToQuadExt<T> {
-    fn to_quad(self) -> Quad<T>;
-}
-
-impl<T> ArrayToQuadExt<T> for [T; 4] {
-    fn to_quad(self) -> Quad<T> {
-        Quad::from_array(self)
-    }
-}
-
 trait SimdExt<T, const LANES: usize>: Sized
 where
     T: SimdElement,
@@ -83,10 +48,3 @@ fn test() {
     x[11] = 230;
     println!("{:#016b}", x);
 }
-
-trait ToParts {
-    type Part;
-
-    fn split(self) -> Quad<Self::Part>;
-    fn combine(parts: Quad<Self::Part>) -> Self;
-}
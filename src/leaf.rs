@@ -5,7 +5,9 @@
 use crate::life::LifeRule;
 use crate::util::{ArrayConcatExt, ArrayUnzipExt, BitGrid, Grid2, ToGrid};
 
-use std::simd::{u16x16, u8x8};
+use std::fmt;
+use std::hash::Hash;
+use std::simd::{u16x16, u8x8, Simd};
 
 use derive_more as dm;
 
@@ -49,6 +51,26 @@ impl Leaf {
             .to_grid()
     }
 
+    /// Joins four leaves into a square of 32 by 32 cells.
+    pub(crate) fn join(Grid2 { nw, ne, sw, se }: Grid2<Leaf>) -> Simd<u32, 32> {
+        let rows = |west: Leaf, east: Leaf| {
+            let (west, east) = (west.cells.to_array(), east.cells.to_array());
+            west.zip(east)
+                .map(|(w, e)| u32::from(w) << 16 | u32::from(e))
+        };
+        Simd::from_array(rows(nw, ne).array_concat(rows(sw, se)))
+    }
+
+    /// Returns the central 16 by 16 cells of a square of 32 by 32 cells,
+    /// which is the part that stays correct for the longest when the square
+    /// is evolved with dead cells around it.
+    pub(crate) fn center_of(square: Simd<u32, 32>) -> Self {
+        let rows: &[u32; 32] = square.as_array();
+        let rows: &[u32; 24] = rows.split_array_ref().0;
+        let rows: &[u32; 16] = rows.rsplit_array_ref().1;
+        Self::new(u16x16::from_array(rows.map(|row| (row >> 8) as u16)))
+    }
+
     pub fn step<R: LifeRule>(&self, rule: &R, ticks: u8) -> Self {
         let mut cells = self.cells;
         for _ in 0..ticks {
@@ -65,6 +87,14 @@ impl Leaf {
         *self == Self::empty()
     }
 
+    /// Returns `true` if all of the alive cells are inside the central 8 by 8
+    /// square.
+    pub fn is_padded(&self) -> bool {
+        let mut center = [0; 16];
+        center[4..12].fill(0x0ff0);
+        self.cells & !u16x16::from_array(center) == u16x16::splat(0)
+    }
+
     pub fn center(&self) -> LeafPart {
         // Start with 16 rows and 16 columns. To isolate the central 8 rows,
         // split off the first 12 rows and then split off the last 8 of those 12
@@ -83,11 +113,214 @@ impl Leaf {
 // `derive_more` macros.
 #[derive(dm::BitAnd, dm::BitOr, dm::BitXor, dm::Not)]
 pub struct LeafPart {
-    cells: u8x8,
+    pub(crate) cells: u8x8,
 }
 
 impl LeafPart {
-    fn new(cells: u8x8) -> Self {
+    pub(crate) fn new(cells: u8x8) -> Self {
         Self { cells }
     }
+
+    pub(crate) fn empty() -> Self {
+        Self::new(u8x8::splat(0))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::empty()
+    }
+}
+
+/// The cells of a leaf of the quadtree, a square of 16 by 16 cells made of
+/// four parts of 8 by 8 cells.
+///
+/// State 0 is the dead state, and every other state counts as alive.
+pub trait Cells: Copy + fmt::Debug + Eq + Hash {
+    /// The number of states a cell can be in.
+    const STATES: u16;
+
+    type Part: Copy + Eq;
+
+    fn empty() -> Self;
+    fn from_parts(parts: Grid2<Self::Part>) -> Self;
+    fn to_parts(self) -> Grid2<Self::Part>;
+
+    /// Returns the central 8 by 8 square.
+    fn center(&self) -> Self::Part;
+
+    /// Returns `true` if all of the alive cells are inside the central 8 by 8
+    /// square.
+    fn is_padded(&self) -> bool;
+
+    /// Returns the state of the cell at `row` and `col`, counting from the
+    /// northwest corner.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the cell is outside the leaf.
+    fn get(&self, row: u8, col: u8) -> u8;
+
+    /// Sets the state of the cell at `row` and `col`, counting from the
+    /// northwest corner.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the cell is outside the leaf or `state` is not less than
+    /// `STATES`.
+    fn set(&mut self, row: u8, col: u8, state: u8);
+
+    /// Returns the leaf with the alive cells of `leaf` in state 1, for the
+    /// pattern formats that only have two states.
+    fn from_binary(leaf: Leaf) -> Self;
+
+    /// Returns the leaf as a two-state `Leaf`, or `None` if some of its cells
+    /// are in a state other than 0 and 1.
+    fn to_binary(self) -> Option<Leaf>;
+
+    fn is_empty(&self) -> bool {
+        *self == Self::empty()
+    }
+
+    fn is_empty_part(part: Self::Part) -> bool {
+        part == Self::empty().center()
+    }
+}
+
+impl Cells for Leaf {
+    const STATES: u16 = 2;
+
+    type Part = LeafPart;
+
+    fn empty() -> Self {
+        Leaf::empty()
+    }
+
+    fn from_parts(parts: Grid2<LeafPart>) -> Self {
+        Leaf::from_parts(parts)
+    }
+
+    fn to_parts(self) -> Grid2<LeafPart> {
+        Leaf::to_parts(self)
+    }
+
+    fn center(&self) -> LeafPart {
+        Leaf::center(self)
+    }
+
+    fn is_padded(&self) -> bool {
+        Leaf::is_padded(self)
+    }
+
+    fn get(&self, row: u8, col: u8) -> u8 {
+        let alive = self.cells.get(row.into(), col.into());
+        u8::from(alive.expect("cell is in bounds"))
+    }
+
+    fn set(&mut self, row: u8, col: u8, state: u8) {
+        assert!(u16::from(state) < Self::STATES, "invalid state {}", state);
+        self.cells = self
+            .cells
+            .set(row.into(), col.into(), state == 1)
+            .expect("cell is in bounds");
+    }
+
+    fn from_binary(leaf: Leaf) -> Self {
+        leaf
+    }
+
+    fn to_binary(self) -> Option<Leaf> {
+        Some(self)
+    }
+}
+
+/// A leaf whose cells have up to `2^N` states, stored as `N` bit-planes, where
+/// `N` is at most 8.
+///
+/// Bit `i` of the state of a cell is stored in `planes[i]`, so a leaf with
+/// only states 0 and 1 has the same first plane as the equivalent `Leaf`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MultiLeaf<const N: usize> {
+    pub planes: [u16x16; N],
+}
+
+impl<const N: usize> MultiLeaf<N> {
+    pub const fn new(planes: [u16x16; N]) -> Self {
+        Self { planes }
+    }
+
+    /// Returns the cells that are in any state other than 0.
+    pub fn alive(&self) -> u16x16 {
+        self.planes
+            .iter()
+            .fold(u16x16::splat(0), |alive, &p| alive | p)
+    }
+
+    pub fn population(&self) -> u128 {
+        u128::from(self.alive().count_ones())
+    }
+}
+
+impl<const N: usize> Cells for MultiLeaf<N> {
+    const STATES: u16 = 1 << N;
+
+    type Part = [LeafPart; N];
+
+    fn empty() -> Self {
+        Self::new([u16x16::splat(0); N])
+    }
+
+    fn from_parts(parts: Grid2<[LeafPart; N]>) -> Self {
+        let mut planes = [u16x16::splat(0); N];
+        for (i, plane) in planes.iter_mut().enumerate() {
+            *plane = Leaf::from_parts(parts.map(|part| part[i])).cells;
+        }
+        Self::new(planes)
+    }
+
+    fn to_parts(self) -> Grid2<[LeafPart; N]> {
+        let parts = self.planes.map(|plane| Leaf::new(plane).to_parts());
+        Grid2 {
+            nw: parts.map(|part| part.nw),
+            ne: parts.map(|part| part.ne),
+            sw: parts.map(|part| part.sw),
+            se: parts.map(|part| part.se),
+        }
+    }
+
+    fn center(&self) -> [LeafPart; N] {
+        self.planes.map(|plane| Leaf::new(plane).center())
+    }
+
+    fn is_padded(&self) -> bool {
+        Leaf::new(self.alive()).is_padded()
+    }
+
+    fn get(&self, row: u8, col: u8) -> u8 {
+        self.planes.iter().enumerate().fold(0, |state, (i, plane)| {
+            let bit = plane
+                .get(row.into(), col.into())
+                .expect("cell is in bounds");
+            state | u8::from(bit) << i
+        })
+    }
+
+    fn set(&mut self, row: u8, col: u8, state: u8) {
+        assert!(u16::from(state) < Self::STATES, "invalid state {}", state);
+        for (i, plane) in self.planes.iter_mut().enumerate() {
+            *plane = plane
+                .set(row.into(), col.into(), state >> i & 1 == 1)
+                .expect("cell is in bounds");
+        }
+    }
+
+    fn from_binary(leaf: Leaf) -> Self {
+        let mut planes = [u16x16::splat(0); N];
+        planes[0] = leaf.cells;
+        Self::new(planes)
+    }
+
+    fn to_binary(self) -> Option<Leaf> {
+        let (first, rest) = self.planes.split_first()?;
+        let is_binary = rest.iter().all(|&plane| plane == u16x16::splat(0));
+        is_binary.then(|| Leaf::new(*first))
+    }
 }
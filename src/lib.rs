@@ -15,4 +15,7 @@
 mod bitgrid;
 pub mod leaf;
 pub mod life;
+pub mod pattern;
+pub mod rule;
+pub mod topology;
 pub mod util;
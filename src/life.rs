@@ -2,14 +2,153 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-use crate::leaf::Leaf;
-use crate::util::{BitGrid, Dir, Grid2, ToGrid};
+use crate::leaf::{Cells, Leaf};
+use crate::topology::Topology;
+use crate::util::{BitGrid, Dir, Grid2, Vec2};
+use fnv::FnvHashMap;
 use indexmap::IndexMap;
 
+use std::cmp::Ordering;
+use std::fmt;
+use std::simd::u16x16;
+
 pub trait LifeRule {
     fn tick<B: BitGrid>(&self, cells: B) -> B;
+
+    /// Returns the farthest distance, in cells, that a cell can see in one
+    /// generation. Rules with a radius of more than 1 can't have `B0`.
+    fn radius(&self) -> u8 {
+        1
+    }
 }
 
+/// A rule that a `Universe` can evolve, acting on leaves of type `Leaf`.
+///
+/// Every `LifeRule` is a rule on the two-state `Leaf`. Rules with more states
+/// act on leaves with more bit-planes.
+pub trait Rule {
+    type Leaf: Cells;
+
+    /// Returns the state of an infinite background after one generation,
+    /// where `true` is alive. Only rules with `B0` turn a dead background
+    /// alive.
+    fn next_background(&self, background: bool) -> bool;
+
+    /// Returns `true` if even and odd generations are computed differently,
+    /// like the two partitions of a block rule.
+    fn alternates(&self) -> bool {
+        false
+    }
+
+    /// Advances `leaf` by one generation, going from `phase` to `next`.
+    /// Whenever the background is alive, the leaf stores the complement of
+    /// the cells.
+    ///
+    /// The cells beyond the edges of the leaf are taken to be dead, so only
+    /// the cells away from the edges are correct.
+    fn tick_leaf(&self, leaf: Self::Leaf, phase: Phase, next: Phase) -> Self::Leaf;
+
+    /// Returns the farthest distance, in cells, that a cell can see in one
+    /// generation, which is at most 8.
+    fn radius(&self) -> u8 {
+        1
+    }
+
+    /// Advances the square of 32 by 32 cells made of `leaves` by `ticks`
+    /// generations, starting on `phase`, and returns its central leaf.
+    ///
+    /// This is only used for rules with a radius of more than 1, which see
+    /// too far for the central leaf to be put together from smaller steps.
+    /// The radius times `ticks` is at most 8.
+    ///
+    /// By default the square is advanced with `tick_leaf`, first on its nine
+    /// overlapping leaves and then on the four leaves made from their
+    /// centers, which is only correct for a radius of at most 4. Rules that
+    /// see farther have to override it.
+    fn step_square(&self, leaves: Grid2<Self::Leaf>, phase: Phase, ticks: u8) -> Self::Leaf {
+        let next = |phase: Phase| Phase {
+            background: self.next_background(phase.background),
+            odd: phase.odd ^ self.alternates(),
+        };
+        let step = |leaf, phase, ticks| {
+            let (mut leaf, mut phase) = (leaf, phase);
+            for _ in 0..ticks {
+                leaf = self.tick_leaf(leaf, phase, next(phase));
+                phase = next(phase);
+            }
+            leaf
+        };
+        // Each stage moves the edge effects at most 4 cells in, to the edge of
+        // the center of a leaf.
+        let ticks0 = if ticks * self.radius() > 4 {
+            ticks / 2
+        } else {
+            0
+        };
+        let phase1 = (0..ticks0).fold(phase, |phase, _| next(phase));
+        leaves.do_it(
+            &mut (),
+            |_, leaf| step(leaf, phase, ticks0).center(),
+            |_, leaf| step(leaf, phase1, ticks - ticks0).center(),
+            |_, leaf| leaf.to_parts(),
+            |_, parts| Self::Leaf::from_parts(parts),
+        )
+    }
+}
+
+impl<R> Rule for R
+where
+    R: LifeRule,
+{
+    type Leaf = Leaf;
+
+    fn next_background(&self, background: bool) -> bool {
+        let cells = self.tick(u16x16::splat(if background { !0 } else { 0 }));
+        // The edges of the leaf see dead cells beyond them, so look at the
+        // middle.
+        cells.get(8, 8) == Some(true)
+    }
+
+    fn tick_leaf(&self, leaf: Leaf, phase: Phase, next: Phase) -> Leaf {
+        if !phase.background && !next.background {
+            return Leaf::new(self.tick(leaf.cells));
+        }
+        let fill = |alive: bool| u16x16::splat(if alive { !0 } else { 0 });
+        Leaf::new(self.tick(leaf.cells ^ fill(phase.background)) ^ fill(next.background))
+    }
+
+    fn radius(&self) -> u8 {
+        LifeRule::radius(self)
+    }
+
+    fn step_square(&self, leaves: Grid2<Leaf>, _: Phase, ticks: u8) -> Leaf {
+        let mut cells = Leaf::join(leaves);
+        for _ in 0..ticks {
+            cells = self.tick(cells);
+        }
+        Leaf::center_of(cells)
+    }
+}
+
+/// The state of a universe at a generation, other than its cells, that
+/// decides how the generation is computed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Phase {
+    /// Whether the background is alive, in which case the leaves store the
+    /// complement of the cells.
+    pub background: bool,
+    /// Whether the generation is odd, for rules that alternate between two
+    /// kinds of generations. Always `false` for other rules.
+    pub odd: bool,
+}
+
+impl Phase {
+    fn index(self) -> usize {
+        usize::from(self.background) | usize::from(self.odd) << 1
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
 pub struct B3S23;
 
 impl LifeRule for B3S23 {
@@ -28,25 +167,24 @@ impl LifeRule for B3S23 {
     }
 }
 
-pub fn do_it(cells: std::simd::u16x16) -> std::simd::u16x16 {
-    B3S23.tick(cells)
-}
-
 #[test]
-fn test() {
+fn test_step() {
     use std::simd::Simd;
     let mut rows = [0u64; 64];
     rows[2] = 0b00100;
     rows[3] = 0b01000;
     rows[4] = 0b01110;
     let x = Simd::from_array(rows);
-    // dbg!(x);
-    // dbg!(B3S23.tick(B3S23.tick(B3S23.tick(B3S23.tick(x)))));
-}
-
-pub enum Cell {
-    Off,
-    On,
+    // After four generations the glider has moved one cell south and one cell
+    // west.
+    let mut moved = [0u64; 64];
+    moved[3] = 0b01000;
+    moved[4] = 0b10000;
+    moved[5] = 0b11100;
+    assert_eq!(
+        B3S23.tick(B3S23.tick(B3S23.tick(B3S23.tick(x)))),
+        Simd::from_array(moved)
+    );
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
@@ -58,12 +196,17 @@ pub struct Branch {
 }
 
 impl Branch {
-    fn from_children(children: Grid2<Node>) -> Self {
+    fn from_children<L: Cells>(gen: Gen, ids: Grid2<NodeId>, children: Grid2<&Node<L>>) -> Self {
+        let child_side_log2 = children.nw.side_log2();
+        assert!(children
+            .to_array()
+            .iter()
+            .all(|node| node.side_log2() == child_side_log2));
         Self {
-            side_log2: children.nw.side_log2() + 1,
+            side_log2: child_side_log2 + 1,
             is_empty: children.to_array().iter().all(|node| node.is_empty()),
-            child_indices: todo!(),
-            gen: todo!(),
+            gen,
+            child_indices: ids.map(|id| id.index),
         }
     }
 }
@@ -74,14 +217,24 @@ impl Branch {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Hash)]
-pub enum Node {
-    Leaf(Leaf),
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Node<L = Leaf> {
+    Leaf(L),
     Branch(Branch),
 }
 
 impl Node {
-    fn as_leaf(&self) -> Option<&Leaf> {
+    /// The side of the largest node, which is enough to advance a pattern by
+    /// any number of generations that fits in a `u64`, even for rules with a
+    /// radius of 8.
+    pub const MAX_SIDE_LOG2: u8 = u64::BITS as u8 + 5;
+}
+
+impl<L> Node<L>
+where
+    L: Cells,
+{
+    fn as_leaf(&self) -> Option<&L> {
         match self {
             Self::Leaf(leaf) => Some(leaf),
             Self::Branch(_) => None,
@@ -110,46 +263,469 @@ impl Node {
     }
 }
 
-pub struct Universe<R = B3S23> {
-    nodes: NodeArena<()>,
+/// Memoized evolution results for a single node.
+///
+/// The results depend on the phase that the step starts on, which only
+/// matters for rules with `B0` and rules that alternate between even and odd
+/// generations. See `Universe` for how those rules are handled.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Memo {
+    /// The center of the node advanced by `2^(side_log2 - 2)` generations,
+    /// starting on each phase, indexed by `Phase::index`.
+    jump: [Option<Idx>; 4],
+    /// The center of the node advanced by `2^step_log2` generations, for each
+    /// smaller step size and starting phase that has been requested.
+    steps: Vec<(u8, Phase, Idx)>,
+}
+
+impl Memo {
+    fn step(&self, step_log2: u8, phase: Phase) -> Option<Idx> {
+        self.steps
+            .iter()
+            .find(|&&(s, p, _)| s == step_log2 && p == phase)
+            .map(|&(_, _, index)| index)
+    }
+}
+
+impl NodeData for Memo {
+    fn for_each_ref(&self, mut f: impl FnMut(Idx)) {
+        self.jump.iter().flatten().copied().for_each(&mut f);
+        self.steps.iter().for_each(|&(_, _, index)| f(index));
+    }
+
+    fn remap(&mut self, mut f: impl FnMut(Idx) -> Option<Idx>) {
+        self.jump = self.jump.map(|index| index.and_then(&mut f));
+        self.steps = self
+            .steps
+            .iter()
+            .filter_map(|&(step_log2, phase, index)| Some((step_log2, phase, f(index)?)))
+            .collect();
+    }
+}
+
+/// A limit on the memory used by the nodes of a `Universe`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Budget {
+    /// At most this many nodes.
+    Nodes(usize),
+    /// At most approximately this many bytes.
+    Bytes(usize),
+}
+
+impl Budget {
+    fn is_exceeded_by<T: Default, L: Cells>(&self, nodes: &NodeArena<T, L>) -> bool {
+        match *self {
+            Self::Nodes(limit) => nodes.len() > limit,
+            Self::Bytes(limit) => nodes.bytes() > limit,
+        }
+    }
+}
+
+/// A HashLife universe that evolves patterns stored in its `NodeArena`.
+///
+/// Rules with `B0` turn the infinite dead background alive, which can't be
+/// stored in a quadtree. Like Golly, the universe instead stores the
+/// complement of the cells whenever the background is alive, so the
+/// background is always stored as empty. Each generation is then computed by
+/// one of a pair of complementary rules, depending on whether the background
+/// is dead or alive. For a rule without `S8` the background alternates between
+/// dead and alive, and for a rule with `S8` it stays alive after the first
+/// generation, which is the same as running the complemented rule.
+///
+/// Rules that alternate between even and odd generations, like block rules,
+/// are handled the same way: the phase of a step is whether the background is
+/// alive together with whether the step starts on an odd generation, and
+/// results are memoized separately for each phase.
+pub struct Universe<R: Rule = B3S23> {
+    nodes: NodeArena<Memo, R::Leaf>,
     rule: R,
+    budget: Option<Budget>,
+    /// The state of the background after a generation, indexed by its state
+    /// before.
+    next_background: [bool; 2],
+    /// The base 2 logarithm of the radius of the rule, rounded up. A node of
+    /// side `2^k` can be advanced by `2^(k - 2 - radius_log2)` generations.
+    radius_log2: u8,
+    topology: Topology,
+}
+
+impl<R> Default for Universe<R>
+where
+    R: Rule + Default,
+{
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl<R> Universe<R>
 where
-    R: LifeRule,
+    R: Rule,
 {
-    pub fn new() -> Self {
-        todo!()
-    }
-
-    pub fn evolve(&mut self, root_id: NodeId, ticks: u64) -> NodeId {
-        let _: Option<_> = try {
-            // NOTE: can only evolve a branch, not a leaf.
-            let branch: Branch = *self.nodes.get_node(root_id)?.as_branch()?;
-            let ticks0 = 0;
-            let ticks1 = 0;
-            if branch.side_log2 == Leaf::SIDE_LOG2 + 1 {
-                // base case: children are leaves
-                let kids: Grid2<Leaf> = branch
-                    .children()
-                    .try_map(|id| self.nodes.get_node(id)?.as_leaf().copied())?;
-                let f0 = |leaf: Leaf| leaf.step(&self.rule, 1).center();
-                let f1 = |leaf: Leaf| leaf.step(&self.rule, 2).center();
-                kids.do_it(f0, f1, Leaf::to_parts, Leaf::from_parts);
-            } else {
-                let kids: Grid2<NodeId> = branch.children();
-                let grandkids: Grid2<Grid2<NodeId>> = branch
-                    .children()
-                    .try_map(|id| self.nodes.get_node(id)?.as_branch())?
-                    .map(|branch| branch.children());
-                let nw = branch.children().nw;
-                let n = self.nodes.make_branch(grandkids.north())?;
-                let ne = branch.children().ne;
-                self.evolve(nw, ticks0);
+    pub fn new() -> Self
+    where
+        R: Default,
+    {
+        Self::with_rule(R::default())
+    }
+
+    pub fn with_rule(rule: R) -> Self {
+        let next_background = [false, true].map(|alive| rule.next_background(alive));
+        let radius = rule.radius();
+        assert!(
+            (1..=8).contains(&radius),
+            "radius {} is out of range",
+            radius
+        );
+        assert!(
+            radius == 1 || !next_background[0],
+            "rules with a radius of more than 1 can't have B0"
+        );
+        Self {
+            nodes: NodeArena::new(),
+            rule,
+            budget: None,
+            next_background,
+            radius_log2: radius.next_power_of_two().trailing_zeros() as u8,
+            topology: Topology::Plane,
+        }
+    }
+
+    pub fn rule(&self) -> &R {
+        &self.rule
+    }
+
+    pub fn budget(&self) -> Option<Budget> {
+        self.budget
+    }
+
+    /// Limits the memory used by the nodes of the universe.
+    ///
+    /// Whenever the budget is exceeded during `evolve`, every node that isn't
+    /// part of the pattern being evolved is removed, along with all memoized
+    /// results. The budget is checked between steps, so it may be exceeded
+    /// temporarily while a single large step is computed.
+    pub fn set_budget(&mut self, budget: Option<Budget>) {
+        self.budget = budget;
+    }
+
+    pub fn topology(&self) -> Topology {
+        self.topology
+    }
+
+    /// Sets the grid that patterns are evolved on. On a bounded grid, any
+    /// cells outside it are removed by `evolve`, which then takes time linear
+    /// in the number of generations rather than logarithmic, see
+    /// `evolve_from`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the grid is bounded and the rule has `B0`.
+    pub fn set_topology(&mut self, topology: Topology) {
+        assert!(
+            topology == Topology::Plane || self.next_background == [false, false],
+            "rules with B0 can't run on a bounded grid"
+        );
+        self.topology = topology;
+    }
+
+    pub fn nodes(&self) -> &NodeArena<Memo, R::Leaf> {
+        &self.nodes
+    }
+
+    pub fn nodes_mut(&mut self) -> &mut NodeArena<Memo, R::Leaf> {
+        &mut self.nodes
+    }
+
+    /// Returns `true` if the background is alive at `generation`, in which
+    /// case the nodes for that generation store the complement of the cells.
+    /// This is only ever the case for rules with `B0`.
+    pub fn background(&self, generation: u64) -> bool {
+        self.background_after(false, generation)
+    }
+
+    /// Returns the state of the background `ticks` generations after it was
+    /// `background`.
+    fn background_after(&self, mut background: bool, ticks: u64) -> bool {
+        // After the first generation the background either stays the same or
+        // alternates, so only the parity of the remaining generations matters.
+        for _ in 0..ticks.min(2 + ticks % 2) {
+            background = self.next_background[usize::from(background)];
+        }
+        background
+    }
+
+    /// Returns the phase at `generation`.
+    fn phase(&self, generation: u64) -> Phase {
+        self.phase_after(Phase::default(), generation)
+    }
+
+    /// Returns the phase `ticks` generations after `phase`.
+    fn phase_after(&self, phase: Phase, ticks: u64) -> Phase {
+        Phase {
+            background: self.background_after(phase.background, ticks),
+            odd: phase.odd ^ (self.rule.alternates() && ticks % 2 == 1),
+        }
+    }
+
+    /// Advances the node `root_id` by `ticks` generations, starting from
+    /// generation 0.
+    ///
+    /// This is the same as `evolve_from(root_id, 0, ticks)`.
+    pub fn evolve(&mut self, root_id: NodeId, ticks: u64) -> Result<NodeId, NodeError> {
+        self.evolve_from(root_id, 0, ticks)
+    }
+
+    /// Advances the node `root_id`, which holds the pattern at `generation`,
+    /// by `ticks` generations.
+    ///
+    /// The number of generations is broken into power-of-two steps, each of
+    /// which is computed with the HashLife algorithm. Before each step the
+    /// root is padded with empty space so that no alive cells can leave it,
+    /// and afterwards any empty border is removed again. The result is
+    /// centered on the same point as the original root. Returns an error if
+    /// `root_id` is invalid.
+    ///
+    /// If the universe has a memory budget, garbage may be collected between
+    /// steps. Any ids other than the returned one may then become invalid.
+    ///
+    /// The generation only matters for rules with `B0`, where it decides
+    /// whether the background is alive, see `background`, and for rules that
+    /// alternate between even and odd generations.
+    ///
+    /// On a bounded grid, see `set_topology`, any cells of `root_id` outside
+    /// the grid are removed first, and the generations are computed in
+    /// blocks so that the cells beyond the edges can be filled in before each
+    /// block. This takes time linear in `ticks`.
+    pub fn evolve_from(
+        &mut self,
+        root_id: NodeId,
+        generation: u64,
+        ticks: u64,
+    ) -> Result<NodeId, NodeError> {
+        let (northwest, southeast) = match self.topology.bounds() {
+            Some(bounds) => bounds,
+            None => return self.evolve_plane(root_id, self.phase(generation), ticks),
+        };
+        let region = (
+            Vec2::new(northwest.x, southeast.y),
+            Vec2::new(southeast.x, northwest.y),
+        );
+        // On a bounded grid the cells beyond the edges are filled in from the
+        // cells they stand for, far enough out that nothing else can reach the
+        // grid during a block, and after the block everything beyond the
+        // edges is removed again. On a torus or a Klein bottle those cells go
+        // on standing for the same cells as they evolve, so a block can be as
+        // long as the grid is wide. Elsewhere they don't, and every
+        // generation is a separate block.
+        let max_block = match self.topology {
+            Topology::Torus { width, height } | Topology::KleinBottle { width, height, .. } => {
+                let side = u64::from(width.min(height)) / u64::from(self.rule.radius());
+                1 << (63 - side.max(1).leading_zeros())
+            }
+            _ => 1,
+        };
+        let mut root = self.nodes.clip(root_id, region)?;
+        let mut phase = self.phase(generation);
+        let mut ticks = ticks;
+        while ticks > 0 {
+            let block = ticks.min(max_block);
+            let border = block as i64 * i64::from(self.rule.radius());
+            let ghosts = self.ghost_cells(root, region, border)?;
+            let ghosts = self.nodes.build_states(ghosts);
+            root = self.nodes.union(root, ghosts)?;
+            root = self.evolve_plane(root, phase, block)?;
+            phase = self.phase_after(phase, block);
+            root = self.nodes.clip(root, region)?;
+            ticks -= block;
+        }
+        let root = self.nodes.shrink(root)?;
+        self.enforce_budget(root)
+    }
+
+    /// Advances the node `root_id` by `ticks` generations on the infinite
+    /// plane, starting on `phase`.
+    fn evolve_plane(
+        &mut self,
+        root_id: NodeId,
+        phase: Phase,
+        ticks: u64,
+    ) -> Result<NodeId, NodeError> {
+        let mut root = root_id;
+        let mut phase = phase;
+        for step_log2 in (0..u64::BITS as u8).filter(|&i| ticks & (1 << i) != 0) {
+            root = self.enforce_budget(root)?;
+            // A padded node of side `2^k` can be advanced by `2^(k-2)`
+            // generations, or fewer for rules with a larger radius, without
+            // its alive cells reaching the edge of the expanded node's
+            // center.
+            root = self.nodes.pad(root, step_log2 + 2 + self.radius_log2)?;
+            let expanded = self.nodes.expand(root);
+            root = self.step(expanded, step_log2, phase);
+            phase = self.phase_after(phase, 1 << step_log2);
+        }
+        let root = self.nodes.shrink(root)?;
+        self.enforce_budget(root)
+    }
+
+    /// Returns the cells within `border` of the edges of the bounded grid
+    /// `region` and beyond them, with the states of the cells on the grid
+    /// that they stand for.
+    fn ghost_cells(
+        &self,
+        root: NodeId,
+        region: (Vec2, Vec2),
+        border: i64,
+    ) -> Result<Vec<(Vec2, u8)>, NodeError> {
+        let (southwest, northeast) = region;
+        let northwest = Vec2::new(southwest.x, northeast.y);
+        let (width, height) = (northeast.x - southwest.x + 1, northeast.y - southwest.y + 1);
+        // Only the cells within the border of the edges can be seen from
+        // beyond them.
+        let mut sources = FnvHashMap::default();
+        let strips = [
+            (southwest, Vec2::new(northeast.x, southwest.y + border - 1)),
+            (Vec2::new(southwest.x, northeast.y - border + 1), northeast),
+            (southwest, Vec2::new(southwest.x + border - 1, northeast.y)),
+            (Vec2::new(northeast.x - border + 1, southwest.y), northeast),
+        ];
+        for strip in strips {
+            self.nodes.for_each_cell_in(root, strip, |cell, state| {
+                sources.insert(cell, state);
+            })?;
+        }
+
+        // Every copy of the grid around it holds each cell of the grid once,
+        // either where it is or mirrored across the middle of the copy.
+        let copies = |side: i64| {
+            let copies = (border + side - 1) / side;
+            -copies..=copies
+        };
+        let mut ghosts = vec![];
+        for (&source, &state) in &sources {
+            let (col, row) = (source.x - northwest.x, northwest.y - source.y);
+            let tiles = copies(width).flat_map(|i| copies(height).map(move |j| (i, j)));
+            for (i, j) in tiles {
+                if (i, j) == (0, 0) {
+                    continue;
+                }
+                let candidates = [col, width - 1 - col]
+                    .into_iter()
+                    .flat_map(|col| [row, height - 1 - row].map(|row| (col, row)));
+                for (col, row) in candidates {
+                    let (col, row) = (col + i * width, row + j * height);
+                    let beyond = |n: i64, side: i64| n < -border || n >= side + border;
+                    if beyond(col, width) || beyond(row, height) {
+                        continue;
+                    }
+                    let cell = northwest + Vec2::new(col, -row);
+                    if self.topology.wrap(cell) == Some(source) {
+                        ghosts.push((cell, state));
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(ghosts)
+    }
+
+    /// Collects garbage if the memory budget has been exceeded, keeping only
+    /// the node `root`. Returns the new id of the root.
+    fn enforce_budget(&mut self, root: NodeId) -> Result<NodeId, NodeError> {
+        match self.budget {
+            Some(budget) if budget.is_exceeded_by(&self.nodes) => {
+                self.nodes.collect(&[root], false)?.get(root)
             }
+            _ => Ok(root),
+        }
+    }
+
+    /// Advances `leaf` by `ticks` generations, starting on `phase`.
+    fn step_leaf(&self, leaf: R::Leaf, phase: Phase, ticks: u8) -> R::Leaf {
+        let (mut leaf, mut phase) = (leaf, phase);
+        for _ in 0..ticks {
+            let next = self.phase_after(phase, 1);
+            leaf = self.rule.tick_leaf(leaf, phase, next);
+            phase = next;
+        }
+        leaf
+    }
+
+    /// Evolves a branch of side `2^k` using the HashLife algorithm.
+    ///
+    /// The result is the central node of side `2^(k-1)` advanced by
+    /// `2^step_log2` generations, where `step_log2` is at most
+    /// `k - 2 - radius_log2`, starting on `phase`.
+    fn step(&mut self, id: NodeId, step_log2: u8, phase: Phase) -> NodeId {
+        let branch = self.nodes.branch(id);
+        let max_step_log2 = branch.side_log2 - 2 - self.radius_log2;
+        debug_assert!(step_log2 <= max_step_log2);
+        let is_jump = step_log2 == max_step_log2;
+
+        // The center of an empty node stays empty forever.
+        if branch.is_empty {
+            return self.nodes.empty(branch.side_log2 - 1);
+        }
+
+        let memo = self.nodes.memo(id);
+        let cached = if is_jump {
+            memo.jump[phase.index()]
+        } else {
+            memo.step(step_log2, phase)
         };
-        todo!()
+        if let Some(index) = cached {
+            return self.nodes.id(index);
+        }
+
+        let result = if branch.side_log2 == Leaf::SIDE_LOG2 + 1 && self.radius_log2 > 0 {
+            // A rule that sees farther than one cell can't advance the nine
+            // overlapping leaves below by even a single generation, so the
+            // whole square is advanced at once.
+            let kids = branch.children().map(|id| self.nodes.leaf(id));
+            let leaf = self.rule.step_square(kids, phase, 1 << step_log2);
+            self.nodes.insert(Node::Leaf(leaf))
+        } else if branch.side_log2 == Leaf::SIDE_LOG2 + 1 {
+            // base case: children are leaves
+            let kids: Grid2<R::Leaf> = branch.children().map(|id| self.nodes.leaf(id));
+            // A leaf of side 16 can be advanced by up to 4 generations before
+            // the edge effects reach its center, so a jump advances by 4 in
+            // each stage. A smaller step only advances in the second stage.
+            let (ticks0, ticks1) = if is_jump { (4, 4) } else { (0, 1 << step_log2) };
+            let phase1 = self.phase_after(phase, ticks0.into());
+            let leaf = kids.do_it(
+                &mut &*self,
+                |this, leaf| this.step_leaf(leaf, phase, ticks0).center(),
+                |this, leaf| this.step_leaf(leaf, phase1, ticks1).center(),
+                |_, leaf| leaf.to_parts(),
+                |_, parts| R::Leaf::from_parts(parts),
+            );
+            self.nodes.insert(Node::Leaf(leaf))
+        } else if is_jump {
+            let phase1 = self.phase_after(phase, 1 << (step_log2 - 1));
+            branch.children().do_it(
+                self,
+                |this, id| this.step(id, step_log2 - 1, phase),
+                |this, id| this.step(id, step_log2 - 1, phase1),
+                |this, id| this.nodes.branch(id).children(),
+                |this, ids| this.nodes.make_branch(ids).expect("child ids are valid"),
+            )
+        } else {
+            branch.children().do_it(
+                self,
+                |this, id| this.nodes.centered(id),
+                |this, id| this.step(id, step_log2, phase),
+                |this, id| this.nodes.branch(id).children(),
+                |this, ids| this.nodes.make_branch(ids).expect("child ids are valid"),
+            )
+        };
+
+        let memo = self.nodes.memo_mut(id);
+        if is_jump {
+            memo.jump[phase.index()] = Some(result.index);
+        } else {
+            memo.steps.push((step_log2, phase, result.index));
+        }
+        result
     }
 }
 
@@ -174,46 +750,174 @@ pub struct Idx(u32);
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct Gen(u32);
 
-pub struct Entry<'a, T> {
-    pub node: &'a Node,
+/// An error from looking up a node in a `NodeArena`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NodeError {
+    /// The id is from a generation of the arena before a garbage collection.
+    Stale(NodeId),
+    /// The id doesn't refer to any node in the arena.
+    Missing(NodeId),
+    /// The node has cells too far from its center for their positions to fit
+    /// in a `Vec2`.
+    OutOfRange(NodeId),
+}
+
+impl fmt::Display for NodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Stale(id) => write!(f, "node id {:?} is stale", id),
+            Self::Missing(id) => write!(f, "node id {:?} does not exist", id),
+            Self::OutOfRange(id) => write!(f, "node id {:?} has cells out of range", id),
+        }
+    }
+}
+
+impl std::error::Error for NodeError {}
+
+/// A mapping from the ids of the nodes that survived a garbage collection to
+/// their new ids.
+pub struct Remap {
+    old_generation: Gen,
+    new_generation: Gen,
+    new_index: Vec<Option<Idx>>,
+}
+
+impl Remap {
+    /// Returns the new id of the node `id` from before the collection.
+    ///
+    /// Ids that are already from the new generation are returned unchanged.
+    /// Returns an error if the node was removed by the collection or if the
+    /// id is older than the collection.
+    pub fn get(&self, id: NodeId) -> Result<NodeId, NodeError> {
+        if id.generation == self.new_generation {
+            return Ok(id);
+        }
+        if id.generation != self.old_generation {
+            return Err(NodeError::Stale(id));
+        }
+        self.new_index
+            .get(id.index.0 as usize)
+            .copied()
+            .flatten()
+            .map(|index| NodeId::new(index, self.new_generation))
+            .ok_or(NodeError::Missing(id))
+    }
+}
+
+pub struct Entry<'a, T, L = Leaf> {
+    pub node: &'a Node<L>,
     pub data: &'a T,
 }
 
-pub struct NodeArena<T> {
+/// Data stored alongside each node of a `NodeArena`.
+///
+/// The data may refer to other nodes of the arena, for example to memoize the
+/// result of evolving the node. The garbage collector uses these references
+/// to decide which nodes to keep.
+pub trait NodeData: Default {
+    /// Calls `f` with the index of every node referred to by the data.
+    fn for_each_ref(&self, f: impl FnMut(Idx));
+
+    /// Replaces every node index in the data by `f(index)`, forgetting any
+    /// reference for which `f` returns `None`.
+    fn remap(&mut self, f: impl FnMut(Idx) -> Option<Idx>);
+}
+
+impl NodeData for () {
+    fn for_each_ref(&self, _: impl FnMut(Idx)) {}
+
+    fn remap(&mut self, _: impl FnMut(Idx) -> Option<Idx>) {}
+}
+
+pub struct NodeArena<T, L = Leaf> {
     generation: Gen,
-    nodes: IndexMap<Node, T, fnv::FnvBuildHasher>,
+    nodes: IndexMap<Node<L>, T, fnv::FnvBuildHasher>,
+    /// The canonical empty node of each side, starting with an empty leaf.
+    empties: Vec<Idx>,
+}
+
+impl<T, L> Default for NodeArena<T, L>
+where
+    T: Default,
+    L: Cells,
+{
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-impl<T> NodeArena<T>
+impl<T, L> NodeArena<T, L>
 where
     T: Default,
+    L: Cells,
 {
     pub fn new() -> Self {
-        Self {
+        let mut arena = Self {
             generation: Gen(0),
             nodes: IndexMap::default(),
+            empties: Vec::new(),
+        };
+        let mut id = arena.insert(Node::Leaf(L::empty()));
+        arena.empties.push(id.index);
+        for _ in Leaf::SIDE_LOG2..Node::MAX_SIDE_LOG2 {
+            id = arena.make_branch(Grid2::repeat(id)).expect("id is valid");
+            arena.empties.push(id.index);
+        }
+        arena
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Returns the approximate number of bytes used by the nodes.
+    ///
+    /// This counts the node, its data, its hash, and its slot in the hash
+    /// table, but not any memory owned by the data on the heap.
+    pub fn bytes(&self) -> usize {
+        let entry = std::mem::size_of::<(Node<L>, T)>() + 2 * std::mem::size_of::<usize>();
+        self.nodes.capacity() * entry
+    }
+
+    /// Checks that the id is from the current generation of the arena.
+    fn check(&self, id: NodeId) -> Result<usize, NodeError> {
+        if id.generation != self.generation {
+            Err(NodeError::Stale(id))
+        } else if id.index.0 as usize >= self.nodes.len() {
+            Err(NodeError::Missing(id))
+        } else {
+            Ok(id.index.0 as usize)
         }
     }
 
-    pub fn entry(&self, id: NodeId) -> Option<Entry<T>> {
-        self.nodes
-            .get_index(id.index.0 as usize)
-            .map(|(node, data)| Entry { node, data })
+    pub fn entry(&self, id: NodeId) -> Result<Entry<T, L>, NodeError> {
+        self.get(id).map(|(node, data)| Entry { node, data })
     }
 
-    pub fn get(&self, id: NodeId) -> Option<(&Node, &T)> {
-        self.nodes.get_index(id.index.0 as usize)
+    pub fn get(&self, id: NodeId) -> Result<(&Node<L>, &T), NodeError> {
+        let index = self.check(id)?;
+        Ok(self.nodes.get_index(index).expect("index is in bounds"))
     }
 
-    pub fn get_node(&self, id: NodeId) -> Option<&Node> {
+    pub fn get_node(&self, id: NodeId) -> Result<&Node<L>, NodeError> {
         self.get(id).map(|(k, _)| k)
     }
 
-    pub fn get_data(&self, id: NodeId) -> Option<&T> {
+    pub fn get_data(&self, id: NodeId) -> Result<&T, NodeError> {
         self.get(id).map(|(_, v)| v)
     }
 
-    pub fn insert(&mut self, node: Node) -> NodeId {
+    pub fn get_data_mut(&mut self, id: NodeId) -> Result<&mut T, NodeError> {
+        let index = self.check(id)?;
+        let (_, v) = self.nodes.get_index_mut(index).expect("index is in bounds");
+        Ok(v)
+    }
+
+    pub fn insert(&mut self, node: Node<L>) -> NodeId {
         let entry = self.nodes.entry(node);
         let index = Idx(entry.index() as u32);
         // Actually insert the node into the indexmap.
@@ -224,16 +928,987 @@ where
         }
     }
 
-    pub fn make_branch(&mut self, child_ids: Grid2<NodeId>) -> Option<NodeId> {
-        let nodes: [&Node; 4] = child_ids.to_array().try_map(|id| self.get_node(id))?;
-        let child_side_log2 = nodes[0].side_log2();
-        assert!(nodes.iter().all(|node| node.side_log2() == child_side_log2));
-        let branch = Branch {
-            is_empty: nodes.iter().all(|node| node.is_empty()),
-            side_log2: child_side_log2 + 1,
-            gen: self.generation,
-            child_indices: child_ids.to_array().map(|id| id.index).to_grid(),
+    pub fn make_branch(&mut self, child_ids: Grid2<NodeId>) -> Result<NodeId, NodeError> {
+        let nodes: Grid2<&Node<L>> = child_ids.try_map(|id| self.get_node(id))?;
+        let branch = Branch::from_children(self.generation, child_ids, nodes);
+        Ok(self.insert(Node::Branch(branch)))
+    }
+
+    /// Returns the id of the node at `index` in the current generation.
+    pub(crate) fn id(&self, index: Idx) -> NodeId {
+        NodeId::new(index, self.generation)
+    }
+
+    // The following accessors are used internally with ids produced by the
+    // arena itself, so a missing node is a bug rather than a user error.
+
+    pub(crate) fn node(&self, id: NodeId) -> Node<L> {
+        *self.get_node(id).expect("node id is valid")
+    }
+
+    pub(crate) fn leaf(&self, id: NodeId) -> L {
+        *self.node(id).as_leaf().expect("node is a leaf")
+    }
+
+    pub(crate) fn branch(&self, id: NodeId) -> Branch {
+        *self.node(id).as_branch().expect("node is a branch")
+    }
+
+    pub(crate) fn memo(&self, id: NodeId) -> &T {
+        self.get_data(id).expect("node id is valid")
+    }
+
+    pub(crate) fn memo_mut(&mut self, id: NodeId) -> &mut T {
+        self.get_data_mut(id).expect("node id is valid")
+    }
+
+    /// Returns the canonical empty node of side `2^side_log2`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `side_log2` is less than `Leaf::SIDE_LOG2` or greater than
+    /// `Node::MAX_SIDE_LOG2`.
+    pub fn empty(&self, side_log2: u8) -> NodeId {
+        assert!((Leaf::SIDE_LOG2..=Node::MAX_SIDE_LOG2).contains(&side_log2));
+        self.id(self.empties[usize::from(side_log2 - Leaf::SIDE_LOG2)])
+    }
+
+    /// Returns the central node of half the side of the node `id`.
+    pub(crate) fn centered(&mut self, id: NodeId) -> NodeId {
+        let branch = self.branch(id);
+        if branch.side_log2 == Leaf::SIDE_LOG2 + 1 {
+            let parts = branch.children().map(|id| self.leaf(id).to_parts());
+            self.insert(Node::Leaf(L::from_parts(parts.center())))
+        } else {
+            let grandkids = branch.children().map(|id| self.branch(id).children());
+            self.make_branch(grandkids.center()).expect("ids are valid")
+        }
+    }
+
+    /// Expands the node `id` until it is at least `2^side_log2` wide and all
+    /// of its alive cells are inside its central square of half the side.
+    pub fn pad(&mut self, id: NodeId, side_log2: u8) -> Result<NodeId, NodeError> {
+        let mut id = id;
+        while self.get_node(id)?.side_log2() < side_log2 || !self.is_padded(id) {
+            id = self.expand(id);
+        }
+        Ok(id)
+    }
+
+    /// Removes empty borders from the node `id` for as long as all of its
+    /// alive cells are inside its central square of half the side.
+    pub fn shrink(&mut self, id: NodeId) -> Result<NodeId, NodeError> {
+        let mut id = id;
+        while self.get_node(id)?.as_branch().is_some() && self.is_padded(id) {
+            id = self.centered(id);
+        }
+        Ok(id)
+    }
+
+    /// Returns `true` if all of the alive cells of the node `id` are inside
+    /// its central square of half the side.
+    pub(crate) fn is_padded(&self, id: NodeId) -> bool {
+        let branch = match self.node(id) {
+            Node::Leaf(leaf) => return leaf.is_padded(),
+            Node::Branch(branch) => branch,
+        };
+        let empty: Grid2<Grid2<bool>> = branch.children().map(|id| match self.node(id) {
+            Node::Leaf(leaf) => leaf.to_parts().map(L::is_empty_part),
+            Node::Branch(branch) => branch.children().map(|id| self.node(id).is_empty()),
+        });
+        // Only the grandchildren at the center may contain alive cells.
+        [
+            [empty.nw.nw, empty.nw.ne, empty.nw.sw],
+            [empty.ne.nw, empty.ne.ne, empty.ne.se],
+            [empty.sw.nw, empty.sw.sw, empty.sw.se],
+            [empty.se.ne, empty.se.sw, empty.se.se],
+        ]
+        .iter()
+        .flatten()
+        .all(|&is_empty| is_empty)
+    }
+
+    /// Returns a node of twice the side of the node `id`, with `id` at its
+    /// center and empty space around it.
+    pub(crate) fn expand(&mut self, id: NodeId) -> NodeId {
+        let children = match self.node(id) {
+            Node::Leaf(leaf) => {
+                let parts = leaf.to_parts();
+                let empty = L::empty().center();
+                Grid2::from_array([
+                    [empty, empty, empty, parts.nw],
+                    [empty, empty, parts.ne, empty],
+                    [empty, parts.sw, empty, empty],
+                    [parts.se, empty, empty, empty],
+                ])
+                .map(|parts| {
+                    let leaf = L::from_parts(Grid2::from_array(parts));
+                    self.insert(Node::Leaf(leaf))
+                })
+            }
+            Node::Branch(branch) => {
+                let kids = branch.children();
+                let empty = self.empty(branch.side_log2 - 1);
+                Grid2::from_array([
+                    [empty, empty, empty, kids.nw],
+                    [empty, empty, kids.ne, empty],
+                    [empty, kids.sw, empty, empty],
+                    [kids.se, empty, empty, empty],
+                ])
+                .map(|ids| {
+                    self.make_branch(Grid2::from_array(ids))
+                        .expect("ids are valid")
+                })
+            }
+        };
+        self.make_branch(children).expect("ids are valid")
+    }
+
+    /// Builds a node containing the given alive cells, centered on the
+    /// origin. Alive cells are in state 1.
+    ///
+    /// Positive `x` is east and positive `y` is north, so a node of side
+    /// `2^k` covers the cells with `-2^(k-1) <= x, y < 2^(k-1)`.
+    pub fn build(&mut self, cells: impl IntoIterator<Item = Vec2>) -> NodeId {
+        self.build_states(cells.into_iter().map(|cell| (cell, 1)))
+    }
+
+    /// Builds a node containing the given cells and their states, centered
+    /// on the origin like `build`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a state is not less than `L::STATES`.
+    pub fn build_states(&mut self, cells: impl IntoIterator<Item = (Vec2, u8)>) -> NodeId {
+        let side = i64::from(Leaf::SIDE);
+        let mut leaves: FnvHashMap<(i64, i64), L> = FnvHashMap::default();
+        for (cell, state) in cells {
+            let key = (cell.x.div_euclid(side), cell.y.div_euclid(side));
+            let row = (side - 1 - cell.y.rem_euclid(side)) as u8;
+            let col = cell.x.rem_euclid(side) as u8;
+            leaves
+                .entry(key)
+                .or_insert_with(L::empty)
+                .set(row, col, state);
+        }
+
+        let mut side_log2 = Leaf::SIDE_LOG2;
+        let mut level: FnvHashMap<(i64, i64), NodeId> = leaves
+            .into_iter()
+            .map(|(key, leaf)| (key, self.insert(Node::Leaf(leaf))))
+            .collect();
+        // Combine the nodes in squares of four until only the four nodes
+        // around the origin are left.
+        let is_central = |&(i, j): &(i64, i64)| (-1..=0).contains(&i) && (-1..=0).contains(&j);
+        loop {
+            let empty = self.empty(side_log2);
+            let mut parents: FnvHashMap<(i64, i64), Grid2<NodeId>> = FnvHashMap::default();
+            for (&(i, j), &id) in &level {
+                let children = parents
+                    .entry((i.div_euclid(2), j.div_euclid(2)))
+                    .or_insert_with(|| Grid2::repeat(empty));
+                match (i.rem_euclid(2) == 0, j.rem_euclid(2) == 1) {
+                    (true, true) => children.nw = id,
+                    (false, true) => children.ne = id,
+                    (true, false) => children.sw = id,
+                    (false, false) => children.se = id,
+                }
+            }
+            if level.keys().all(is_central) {
+                let children = Grid2 {
+                    nw: level.get(&(-1, 0)).copied().unwrap_or(empty),
+                    ne: level.get(&(0, 0)).copied().unwrap_or(empty),
+                    sw: level.get(&(-1, -1)).copied().unwrap_or(empty),
+                    se: level.get(&(0, -1)).copied().unwrap_or(empty),
+                };
+                return self.make_branch(children).expect("ids are valid");
+            }
+            level = parents
+                .into_iter()
+                .map(|(key, children)| (key, self.make_branch(children).expect("ids are valid")))
+                .collect();
+            side_log2 += 1;
+        }
+    }
+
+    /// Calls `f` with the position of every alive cell in the node `id`,
+    /// relative to its center. Empty nodes are skipped entirely.
+    pub fn for_each_alive(&self, id: NodeId, mut f: impl FnMut(Vec2)) -> Result<(), NodeError> {
+        self.for_each_cell(id, |cell, _| f(cell))
+    }
+
+    /// Calls `f` with the position and state of every cell in the node `id`
+    /// that isn't in state 0, like `for_each_alive`.
+    ///
+    /// Returns an error without calling `f` if some of those cells are too far
+    /// from the center of the node for their positions to fit in `i64`, which
+    /// can happen to nodes that have been evolved for long enough.
+    pub fn for_each_cell(&self, id: NodeId, mut f: impl FnMut(Vec2, u8)) -> Result<(), NodeError> {
+        self.get_node(id)?;
+        if !self.fits(id, (0, 0)) {
+            return Err(NodeError::OutOfRange(id));
+        }
+        let everywhere = (Vec2::new(i64::MIN, i64::MIN), Vec2::new(i64::MAX, i64::MAX));
+        self.for_each_cell_in(id, everywhere, &mut f)
+    }
+
+    /// Calls `f` like `for_each_cell`, but only for the cells between the
+    /// southwest and northeast corners of `region`, inclusive. Nodes outside
+    /// the region are skipped entirely.
+    pub(crate) fn for_each_cell_in(
+        &self,
+        id: NodeId,
+        region: (Vec2, Vec2),
+        mut f: impl FnMut(Vec2, u8),
+    ) -> Result<(), NodeError> {
+        self.get_node(id)?;
+        self.visit_cells(id, (0, 0), widen_region(region), &mut f);
+        Ok(())
+    }
+
+    /// Returns `true` if every cell of the node `id` centered on `center` that
+    /// isn't in state 0 has a position that fits in `i64`.
+    fn fits(&self, id: NodeId, center: Wide) -> bool {
+        let node = self.node(id);
+        let (southwest, northeast) = extent(center, node.side_log2());
+        if node.is_empty() || contains(I64_PLANE, southwest) && contains(I64_PLANE, northeast) {
+            return true;
+        }
+        match node {
+            // Leaves never straddle the edge of the `i64` plane.
+            Node::Leaf(_) => false,
+            Node::Branch(branch) => {
+                let quarter = 1 << (branch.side_log2 - 2);
+                let kids = branch.children();
+                self.fits(kids.nw, offset(center, -quarter, quarter))
+                    && self.fits(kids.ne, offset(center, quarter, quarter))
+                    && self.fits(kids.sw, offset(center, -quarter, -quarter))
+                    && self.fits(kids.se, offset(center, quarter, -quarter))
+            }
+        }
+    }
+
+    fn visit_cells(
+        &self,
+        id: NodeId,
+        center: Wide,
+        region: (Wide, Wide),
+        f: &mut impl FnMut(Vec2, u8),
+    ) {
+        let node = self.node(id);
+        if node.is_empty() || !overlaps(extent(center, node.side_log2()), region) {
+            return;
+        }
+        match node {
+            Node::Leaf(leaf) => {
+                for row in 0..Leaf::SIDE {
+                    for col in 0..Leaf::SIDE {
+                        let state = leaf.get(row, col);
+                        let cell = leaf_cell(center, row, col);
+                        if state != 0 && contains(region, cell) {
+                            // The region is made of `i64` positions.
+                            f(Vec2::new(cell.0 as i64, cell.1 as i64), state);
+                        }
+                    }
+                }
+            }
+            Node::Branch(branch) => {
+                let quarter = 1 << (branch.side_log2 - 2);
+                let kids = branch.children();
+                self.visit_cells(kids.nw, offset(center, -quarter, quarter), region, f);
+                self.visit_cells(kids.ne, offset(center, quarter, quarter), region, f);
+                self.visit_cells(kids.sw, offset(center, -quarter, -quarter), region, f);
+                self.visit_cells(kids.se, offset(center, quarter, -quarter), region, f);
+            }
+        }
+    }
+
+    /// Returns the node `id` with every cell outside the southwest and
+    /// northeast corners of `region` set to state 0.
+    pub(crate) fn clip(&mut self, id: NodeId, region: (Vec2, Vec2)) -> Result<NodeId, NodeError> {
+        self.get_node(id)?;
+        Ok(self.clip_at(id, (0, 0), widen_region(region)))
+    }
+
+    fn clip_at(&mut self, id: NodeId, center: Wide, region: (Wide, Wide)) -> NodeId {
+        let node = self.node(id);
+        let side_log2 = node.side_log2();
+        let (southwest, northeast) = extent(center, side_log2);
+        if node.is_empty() || contains(region, southwest) && contains(region, northeast) {
+            return id;
+        }
+        if !overlaps((southwest, northeast), region) {
+            return self.empty(side_log2);
+        }
+        match node {
+            Node::Leaf(mut leaf) => {
+                for row in 0..Leaf::SIDE {
+                    for col in 0..Leaf::SIDE {
+                        if !contains(region, leaf_cell(center, row, col)) {
+                            leaf.set(row, col, 0);
+                        }
+                    }
+                }
+                self.insert(Node::Leaf(leaf))
+            }
+            Node::Branch(branch) => {
+                let quarter = 1 << (branch.side_log2 - 2);
+                let kids = branch.children();
+                let children = Grid2 {
+                    nw: self.clip_at(kids.nw, offset(center, -quarter, quarter), region),
+                    ne: self.clip_at(kids.ne, offset(center, quarter, quarter), region),
+                    sw: self.clip_at(kids.sw, offset(center, -quarter, -quarter), region),
+                    se: self.clip_at(kids.se, offset(center, quarter, -quarter), region),
+                };
+                self.make_branch(children).expect("ids are valid")
+            }
+        }
+    }
+
+    /// Returns a node with the cells of both of the nodes `a` and `b`, which
+    /// are both centered on the origin. Where both have a cell that isn't in
+    /// state 0, the one from `b` is kept.
+    pub(crate) fn union(&mut self, a: NodeId, b: NodeId) -> Result<NodeId, NodeError> {
+        let (mut a, mut b) = (a, b);
+        loop {
+            let (a_side_log2, b_side_log2) =
+                (self.get_node(a)?.side_log2(), self.get_node(b)?.side_log2());
+            match a_side_log2.cmp(&b_side_log2) {
+                Ordering::Less => a = self.expand(a),
+                Ordering::Greater => b = self.expand(b),
+                Ordering::Equal => return Ok(self.union_same(a, b)),
+            }
+        }
+    }
+
+    fn union_same(&mut self, a: NodeId, b: NodeId) -> NodeId {
+        match (self.node(a), self.node(b)) {
+            (node, _) if node.is_empty() => b,
+            (_, node) if node.is_empty() => a,
+            (Node::Leaf(mut leaf), Node::Leaf(other)) => {
+                for row in 0..Leaf::SIDE {
+                    for col in 0..Leaf::SIDE {
+                        let state = other.get(row, col);
+                        if state != 0 {
+                            leaf.set(row, col, state);
+                        }
+                    }
+                }
+                self.insert(Node::Leaf(leaf))
+            }
+            (Node::Branch(branch), Node::Branch(other)) => {
+                let (kids, others) = (branch.children(), other.children());
+                let children = Grid2 {
+                    nw: self.union_same(kids.nw, others.nw),
+                    ne: self.union_same(kids.ne, others.ne),
+                    sw: self.union_same(kids.sw, others.sw),
+                    se: self.union_same(kids.se, others.se),
+                };
+                self.make_branch(children).expect("ids are valid")
+            }
+            _ => unreachable!("nodes have the same side"),
+        }
+    }
+}
+
+/// A position as `x` and `y` in `i128`, which can hold the corners of the
+/// largest nodes while `Vec2` can't.
+type Wide = (i128, i128);
+
+/// The southwest and northeast corners of the cells whose positions fit in
+/// `Vec2`.
+const I64_PLANE: (Wide, Wide) = (
+    (i64::MIN as i128, i64::MIN as i128),
+    (i64::MAX as i128, i64::MAX as i128),
+);
+
+fn widen_region((southwest, northeast): (Vec2, Vec2)) -> (Wide, Wide) {
+    (
+        (southwest.x.into(), southwest.y.into()),
+        (northeast.x.into(), northeast.y.into()),
+    )
+}
+
+fn offset((x, y): Wide, dx: i128, dy: i128) -> Wide {
+    (x + dx, y + dy)
+}
+
+/// Returns the position of the cell at `row` and `col` of a leaf centered on
+/// `center`.
+fn leaf_cell(center: Wide, row: u8, col: u8) -> Wide {
+    let half = i128::from(Leaf::SIDE / 2);
+    offset(center, i128::from(col) - half, half - 1 - i128::from(row))
+}
+
+/// Returns the southwest and northeast corners of a node of side
+/// `2^side_log2` centered on `center`.
+fn extent(center: Wide, side_log2: u8) -> (Wide, Wide) {
+    let half = 1 << (side_log2 - 1);
+    (
+        offset(center, -half, -half),
+        offset(center, half - 1, half - 1),
+    )
+}
+
+/// Returns `true` if `cell` is between the southwest and northeast corners of
+/// `region`, inclusive.
+fn contains((southwest, northeast): (Wide, Wide), cell: Wide) -> bool {
+    (southwest.0..=northeast.0).contains(&cell.0) && (southwest.1..=northeast.1).contains(&cell.1)
+}
+
+/// Returns `true` if the two regions, given by their southwest and northeast
+/// corners, have any cells in common.
+fn overlaps(a: (Wide, Wide), b: (Wide, Wide)) -> bool {
+    a.0 .0 <= b.1 .0 && b.0 .0 <= a.1 .0 && a.0 .1 <= b.1 .1 && b.0 .1 <= a.1 .1
+}
+
+impl<T, L> NodeArena<T, L>
+where
+    T: NodeData,
+    L: Cells,
+{
+    /// Removes every node that is not reachable from `roots`.
+    ///
+    /// If `keep_data` is `true`, the nodes referred to by the data of a
+    /// reachable node are also reachable; otherwise all of the data is reset.
+    /// The remaining nodes are compacted and the generation of the arena is
+    /// bumped, so any id from before the collection becomes stale. Returns a
+    /// mapping to the new ids of the remaining nodes, including the roots, or
+    /// an error if any of the roots is invalid.
+    pub fn collect(&mut self, roots: &[NodeId], keep_data: bool) -> Result<Remap, NodeError> {
+        for &root in roots {
+            self.get_node(root)?;
+        }
+
+        // Mark every reachable node.
+        let mut marked = vec![false; self.nodes.len()];
+        let mut stack: Vec<Idx> = roots.iter().map(|id| id.index).collect();
+        stack.extend(&self.empties);
+        while let Some(index) = stack.pop() {
+            if std::mem::replace(&mut marked[index.0 as usize], true) {
+                continue;
+            }
+            let (node, data) = self
+                .nodes
+                .get_index(index.0 as usize)
+                .expect("index is valid");
+            if let Node::Branch(branch) = node {
+                stack.extend(branch.child_indices.to_array());
+            }
+            if keep_data {
+                data.for_each_ref(|index| stack.push(index));
+            }
+        }
+
+        // Move the marked nodes into a new map. The children of a branch are
+        // always inserted before the branch itself, so they have already been
+        // moved by the time the branch is.
+        let generation = Gen(self.generation.0.wrapping_add(1));
+        let old_nodes = std::mem::take(&mut self.nodes);
+        let mut new_index: Vec<Option<Idx>> = vec![None; old_nodes.len()];
+        for (old, (node, data)) in old_nodes.into_iter().enumerate() {
+            if !marked[old] {
+                continue;
+            }
+            let node = match node {
+                Node::Leaf(leaf) => Node::Leaf(leaf),
+                Node::Branch(branch) => Node::Branch(Branch {
+                    gen: generation,
+                    child_indices: branch
+                        .child_indices
+                        .map(|index| new_index[index.0 as usize].expect("child was moved")),
+                    ..branch
+                }),
+            };
+            let data = if keep_data { data } else { T::default() };
+            let (index, _) = self.nodes.insert_full(node, data);
+            new_index[old] = Some(Idx(index as u32));
+        }
+        let old_generation = std::mem::replace(&mut self.generation, generation);
+
+        // Forget any references to nodes that were removed.
+        if keep_data {
+            for (_, data) in self.nodes.iter_mut() {
+                data.remap(|index| new_index[index.0 as usize]);
+            }
+        }
+        for index in &mut self.empties {
+            *index = new_index[index.0 as usize].expect("empty nodes are kept");
+        }
+
+        Ok(Remap {
+            old_generation,
+            new_generation: generation,
+            new_index,
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn soup(seed: u64, mask: u64) -> [u64; 64] {
+    // A xorshift generator is plenty random for test patterns.
+    let mut state = seed;
+    [(); 64].map(|_| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state & mask
+    })
+}
+
+#[cfg(test)]
+fn build(
+    nodes: &mut NodeArena<Memo>,
+    rows: &[u64; 64],
+    side_log2: u8,
+    row: usize,
+    col: usize,
+) -> NodeId {
+    if side_log2 == Leaf::SIDE_LOG2 {
+        let mut cells = [0; 16];
+        for (i, cell) in cells.iter_mut().enumerate() {
+            *cell = (rows[row + i] >> (48 - col)) as u16;
+        }
+        return nodes.insert(Node::Leaf(Leaf::new(u16x16::from_array(cells))));
+    }
+    let half = 1 << (side_log2 - 1);
+    let children = Grid2 {
+        nw: build(nodes, rows, side_log2 - 1, row, col),
+        ne: build(nodes, rows, side_log2 - 1, row, col + half),
+        sw: build(nodes, rows, side_log2 - 1, row + half, col),
+        se: build(nodes, rows, side_log2 - 1, row + half, col + half),
+    };
+    nodes.make_branch(children).unwrap()
+}
+
+#[cfg(test)]
+fn read(nodes: &NodeArena<Memo>, id: NodeId, row: usize, col: usize) -> bool {
+    match nodes.node(id) {
+        Node::Leaf(leaf) => leaf.cells.get(row, col).unwrap(),
+        Node::Branch(branch) => {
+            let half = 1 << (branch.side_log2 - 1);
+            let kids = branch.children();
+            match (row < half, col < half) {
+                (true, true) => read(nodes, kids.nw, row, col),
+                (true, false) => read(nodes, kids.ne, row, col - half),
+                (false, true) => read(nodes, kids.sw, row - half, col),
+                (false, false) => read(nodes, kids.se, row - half, col - half),
+            }
+        }
+    }
+}
+
+/// Returns the positions of the alive cells in the node `id` as `(row, col)`
+/// offsets from its center.
+#[cfg(test)]
+fn alive(nodes: &NodeArena<Memo>, id: NodeId) -> Vec<(i64, i64)> {
+    let side = 1 << nodes.node(id).side_log2();
+    let mut cells = vec![];
+    for row in 0..side {
+        for col in 0..side {
+            if read(nodes, id, row, col) {
+                cells.push((row as i64 - side as i64 / 2, col as i64 - side as i64 / 2));
+            }
+        }
+    }
+    cells
+}
+
+#[test]
+fn test_jump_matches_direct_evolution() {
+    use std::simd::Simd;
+
+    let rows = soup(0x2545_f491_4f6c_dd1d, u64::MAX);
+    let mut universe = Universe::<B3S23>::new();
+    let root = build(universe.nodes_mut(), &rows, 6, 0, 0);
+    let result = universe.step(root, 4, Phase::default());
+    assert_eq!(universe.nodes().node(result).side_log2(), 5);
+
+    let expected = (0..16).fold(Simd::from_array(rows), |cells, _| B3S23.tick(cells));
+    for row in 0..32 {
+        for col in 0..32 {
+            assert_eq!(
+                read(universe.nodes(), result, row, col),
+                expected.get(row + 16, col + 16).unwrap()
+            );
+        }
+    }
+
+    // Evolving the same node again reuses the memoized result.
+    let len = universe.nodes().len();
+    assert_eq!(universe.step(root, 4, Phase::default()), result);
+    assert_eq!(universe.nodes().len(), len);
+}
+
+#[test]
+fn test_evolve_arbitrary_ticks() {
+    use std::simd::Simd;
+
+    // A 16 by 16 soup in the middle of a 64 by 64 grid can grow by at most 16
+    // cells on each side in 16 generations, so it never wraps around.
+    let mut rows = soup(0x9e37_79b9_7f4a_7c15, 0xffff << 24);
+    rows[..24].fill(0);
+    rows[40..].fill(0);
+
+    let mut universe = Universe::<B3S23>::new();
+    let root = build(universe.nodes_mut(), &rows, 6, 0, 0);
+    for ticks in [0, 1, 3, 5, 6, 11, 16] {
+        let result = universe.evolve(root, ticks).unwrap();
+        let expected = (0..ticks).fold(Simd::from_array(rows), |cells, _| B3S23.tick(cells));
+        let mut expected_alive = vec![];
+        for row in 0..64 {
+            for col in 0..64 {
+                if expected.get(row, col).unwrap() {
+                    expected_alive.push((row as i64 - 32, col as i64 - 32));
+                }
+            }
+        }
+        assert_eq!(alive(universe.nodes(), result), expected_alive);
+    }
+}
+
+#[test]
+fn test_empty_nodes_are_shared() {
+    let mut universe = Universe::<B3S23>::new();
+    let len = universe.nodes().len();
+    for side_log2 in Leaf::SIDE_LOG2..=Node::MAX_SIDE_LOG2 {
+        let empty = universe.nodes().empty(side_log2);
+        let node = universe.nodes().node(empty);
+        assert!(node.is_empty());
+        assert_eq!(node.side_log2(), side_log2);
+    }
+
+    // Evolving an empty node never creates any new nodes.
+    let root = universe.nodes().empty(40);
+    let result = universe.evolve(root, u64::MAX).unwrap();
+    assert!(universe.nodes().node(result).is_empty());
+    assert_eq!(universe.nodes().len(), len);
+}
+
+#[test]
+fn test_b0_rules() {
+    use crate::rule::Totalistic;
+
+    let mut rows = soup(0x2545_f491_4f6c_dd1d, 0xffff << 24);
+    rows[..24].fill(0);
+    rows[40..].fill(0);
+
+    // Evolves the rows the slow way, treating every cell outside the grid as
+    // part of the background.
+    let tick = |rule: &Totalistic, cells: &[[bool; 64]; 64], background: bool| {
+        let get = |row: i64, col: i64| match (usize::try_from(row), usize::try_from(col)) {
+            (Ok(row), Ok(col)) if row < 64 && col < 64 => cells[row][col],
+            _ => background,
         };
-        Some(self.insert(Node::Branch(branch)))
+        let mut next = [[false; 64]; 64];
+        for (row, next) in next.iter_mut().enumerate() {
+            for (col, next) in next.iter_mut().enumerate() {
+                let (row, col) = (row as i64, col as i64);
+                let count = [-1, 0, 1]
+                    .into_iter()
+                    .flat_map(|dy| [-1, 0, 1].map(|dx| (dy, dx)))
+                    .filter(|&(dy, dx)| (dy, dx) != (0, 0) && get(row + dy, col + dx))
+                    .count() as u8;
+                *next = if get(row, col) {
+                    rule.is_survival(count)
+                } else {
+                    rule.is_birth(count)
+                };
+            }
+        }
+        next
+    };
+
+    for (rule, backgrounds) in [
+        ("B0123478/S34678", [false, true, true, true]),
+        ("B013/S0124", [false, true, false, true]),
+    ] {
+        let rule: Totalistic = rule.parse().unwrap();
+        let mut universe = Universe::with_rule(rule);
+        for (generation, &background) in backgrounds.iter().enumerate() {
+            assert_eq!(universe.background(generation as u64), background);
+        }
+
+        let root = build(universe.nodes_mut(), &rows, 6, 0, 0);
+        let mut cells = [[false; 64]; 64];
+        for (row, cells) in cells.iter_mut().enumerate() {
+            for (col, cell) in cells.iter_mut().enumerate() {
+                *cell = rows[row] >> (63 - col) & 1 == 1;
+            }
+        }
+        let mut background = false;
+        for ticks in 1..=16 {
+            cells = tick(&rule, &cells, background);
+            background = universe.background(ticks);
+            // The nodes store the complement of the cells when the background is
+            // alive.
+            let mut expected = vec![];
+            for (row, cells) in cells.iter().enumerate() {
+                for (col, &cell) in cells.iter().enumerate() {
+                    if cell != background {
+                        expected.push((row as i64 - 32, col as i64 - 32));
+                    }
+                }
+            }
+            let result = universe.evolve(root, ticks).unwrap();
+            assert_eq!(alive(universe.nodes(), result), expected, "{}", ticks);
+        }
+
+        // Evolving in several parts gives the same result.
+        let first = universe.evolve(root, 3).unwrap();
+        let second = universe.evolve_from(first, 3, 10).unwrap();
+        assert_eq!(second, universe.evolve(root, 13).unwrap());
+    }
+
+    // After the first generation, a rule with `B0` and `S8` is the same as
+    // the complemented rule.
+    let mut universe = Universe::with_rule("B0123478/S34678".parse::<Totalistic>().unwrap());
+    let root = build(universe.nodes_mut(), &rows, 6, 0, 0);
+    let first = universe.evolve(root, 1).unwrap();
+    let expected = universe.evolve(root, 11).unwrap();
+    let mut complemented = Universe::with_rule("B3678/S23".parse::<Totalistic>().unwrap());
+    let cells = alive(universe.nodes(), first)
+        .into_iter()
+        .map(|(row, col)| Vec2::new(col, -1 - row));
+    let first = complemented.nodes_mut().build(cells);
+    let result = complemented.evolve(first, 10).unwrap();
+    assert_eq!(
+        alive(complemented.nodes(), result),
+        alive(universe.nodes(), expected)
+    );
+}
+
+#[test]
+fn test_evolve_pads_and_shrinks() {
+    let mut rows = [0; 64];
+    // A glider travelling southeast, in the middle of a 32 by 32 node.
+    rows[15] = 0b010 << 47;
+    rows[16] = 0b001 << 47;
+    rows[17] = 0b111 << 47;
+    let mut universe = Universe::<B3S23>::new();
+    let glider = build(universe.nodes_mut(), &rows, 5, 0, 0);
+    let before = alive(universe.nodes(), glider);
+
+    // The glider moves one cell diagonally every four generations, so it
+    // would leave the original node long before 400 generations.
+    let result = universe.evolve(glider, 400).unwrap();
+    let after = alive(universe.nodes(), result);
+    assert_eq!(after.len(), 5);
+    assert_eq!(
+        after,
+        before
+            .iter()
+            .map(|&(row, col)| (row + 100, col + 100))
+            .collect::<Vec<_>>()
+    );
+
+    // A blinker stays put, so the root should not keep growing.
+    let mut rows = [0; 64];
+    rows[16] = 0b111 << 47;
+    let blinker = build(universe.nodes_mut(), &rows, 5, 0, 0);
+    let result = universe.evolve(blinker, 1 << 20).unwrap();
+    assert!(universe.nodes().node(result).side_log2() <= 5);
+    assert_eq!(
+        alive(universe.nodes(), result),
+        alive(universe.nodes(), blinker)
+    );
+}
+
+#[test]
+fn test_default_step_square() {
+    use crate::rule::LargerThanLife;
+
+    // A rule that only advances single leaves, which leaves the squares of
+    // rules with a larger radius to the default `step_square`.
+    struct LeafOnly(LargerThanLife);
+
+    impl Rule for LeafOnly {
+        type Leaf = Leaf;
+
+        fn next_background(&self, _: bool) -> bool {
+            false
+        }
+
+        fn tick_leaf(&self, leaf: Leaf, _: Phase, _: Phase) -> Leaf {
+            Leaf::new(self.0.tick(leaf.cells))
+        }
+
+        fn radius(&self) -> u8 {
+            LifeRule::radius(&self.0)
+        }
+    }
+
+    fn run<R: Rule<Leaf = Leaf>>(mut universe: Universe<R>, cells: &[Vec2]) -> [Vec<Vec2>; 5] {
+        let root = universe.nodes_mut().build(cells.iter().copied());
+        [1, 2, 3, 4, 30].map(|ticks| {
+            let result = universe.evolve(root, ticks).unwrap();
+            let mut cells = vec![];
+            universe
+                .nodes()
+                .for_each_alive(result, |cell| cells.push(cell))
+                .unwrap();
+            cells.sort_by_key(|cell| (cell.x, cell.y));
+            cells
+        })
+    }
+
+    for (seed, rule) in ["R2,C0,M0,S3..8,B5..7,NM", "R4,C0,M0,S15..30,B20..26,NM"]
+        .into_iter()
+        .enumerate()
+    {
+        let rule: LargerThanLife = rule.parse().unwrap();
+        let rows = soup(seed as u64 + 1, 0xffff << 24);
+        let mut cells = vec![];
+        for (row, &bits) in rows.iter().enumerate().skip(24).take(16) {
+            for col in (0..64).filter(|col| bits >> (63 - col) & 1 == 1) {
+                cells.push(Vec2::new(col - 32, 31 - row as i64));
+            }
+        }
+        let expected = run(Universe::with_rule(rule), &cells);
+        let actual = run(Universe::with_rule(LeafOnly(rule)), &cells);
+        assert!(!expected[4].is_empty());
+        assert_eq!(actual, expected);
+    }
+}
+
+#[test]
+fn test_far_cells() {
+    use crate::rule::Wolfram;
+
+    // A glider evolved for as long as possible ends up near the edge of the
+    // `i64` plane, in a node much larger than it.
+    let mut universe = Universe::<B3S23>::new();
+    let glider = [(0, 1), (1, 0), (-1, -1), (0, -1), (1, -1)].map(|(x, y)| Vec2::new(x, y));
+    let glider = universe.nodes_mut().build(glider);
+    let cells = |universe: &Universe<B3S23>, root| {
+        let mut cells = vec![];
+        universe
+            .nodes()
+            .for_each_alive(root, |cell| cells.push(cell))
+            .unwrap();
+        cells.sort_by_key(|cell| (cell.x, cell.y));
+        cells
+    };
+    let third = universe.evolve(glider, 3).unwrap();
+    let fourth = universe.evolve(glider, 4).unwrap();
+    let step = cells(&universe, fourth)[0] - cells(&universe, glider)[0];
+    let result = universe.evolve(glider, u64::MAX).unwrap();
+    assert!(universe.nodes().node(result).side_log2() >= 64);
+    let k = (u64::MAX / 4) as i64;
+    let expected: Vec<_> = cells(&universe, third)
+        .into_iter()
+        .map(|cell| cell + Vec2::new(step.x * k, step.y * k))
+        .collect();
+    assert_eq!(cells(&universe, result), expected);
+    let corner = |pick: fn(i64, i64) -> i64| {
+        expected.iter().fold(expected[0], |a, b| {
+            Vec2::new(pick(a.x, b.x), pick(a.y, b.y))
+        })
+    };
+    let region = (corner(i64::min), corner(i64::max));
+    let clipped = universe.nodes_mut().clip(result, region).unwrap();
+    assert_eq!(cells(&universe, clipped), expected);
+
+    // A spacetime diagram that grows by a row every generation has cells
+    // beyond the `i64` plane.
+    let mut universe = Universe::with_rule(Wolfram::elementary(254));
+    let root = universe.spacetime([0], u64::MAX).unwrap();
+    assert_eq!(
+        universe.nodes().for_each_alive(root, |_| {}),
+        Err(NodeError::OutOfRange(root))
+    );
+    let mut population = 0;
+    let region = (Vec2::new(-1, -1), Vec2::new(1, 0));
+    universe
+        .nodes()
+        .for_each_cell_in(root, region, |_, _| population += 1)
+        .unwrap();
+    assert_eq!(population, 4);
+}
+
+#[test]
+fn test_collect() {
+    let rows = soup(0x1234_5678_9abc_def0, 0xffff << 24);
+    let mut universe = Universe::<B3S23>::new();
+    let root = build(universe.nodes_mut(), &rows, 6, 0, 0);
+    let before = alive(universe.nodes(), root);
+    let jump = universe.step(root, 4, Phase::default());
+    let jump_alive = alive(universe.nodes(), jump);
+    let evolved = universe.evolve(root, 100).unwrap();
+    let after = alive(universe.nodes(), evolved);
+    let len = universe.nodes().len();
+
+    // Keeping the memoized results keeps enough nodes to jump again without
+    // creating any new ones.
+    let root = universe
+        .nodes_mut()
+        .collect(&[root], true)
+        .unwrap()
+        .get(root)
+        .unwrap();
+    assert!(universe.nodes().len() < len);
+    assert_eq!(alive(universe.nodes(), root), before);
+    let len = universe.nodes().len();
+    let jump = universe.step(root, 4, Phase::default());
+    assert_eq!(alive(universe.nodes(), jump), jump_alive);
+    assert_eq!(universe.nodes().len(), len);
+
+    // Without the memoized results only the structure of the roots is kept.
+    let root = universe
+        .nodes_mut()
+        .collect(&[root], false)
+        .unwrap()
+        .get(root)
+        .unwrap();
+    assert!(universe.nodes().len() < len);
+    assert_eq!(alive(universe.nodes(), root), before);
+    let evolved = universe.evolve(root, 100).unwrap();
+    assert_eq!(alive(universe.nodes(), evolved), after);
+}
+
+#[test]
+fn test_stale_ids() {
+    let rows = soup(0x1234_5678_9abc_def0, 0xffff << 24);
+    let mut universe = Universe::<B3S23>::new();
+    let root = build(universe.nodes_mut(), &rows, 6, 0, 0);
+    let evolved = universe.evolve(root, 100).unwrap();
+    let evolved_alive = alive(universe.nodes(), evolved);
+
+    let remap = universe.nodes_mut().collect(&[evolved], false).unwrap();
+    assert_eq!(universe.nodes().get_node(root), Err(NodeError::Stale(root)));
+    assert_eq!(universe.evolve(evolved, 1), Err(NodeError::Stale(evolved)));
+    assert_eq!(remap.get(root), Err(NodeError::Missing(root)));
+    let new_evolved = remap.get(evolved).unwrap();
+    assert_eq!(remap.get(new_evolved), Ok(new_evolved));
+    assert_eq!(alive(universe.nodes(), new_evolved), evolved_alive);
+
+    // A remapping only applies to the collection that produced it.
+    let next = universe.nodes_mut().collect(&[new_evolved], false).unwrap();
+    assert_eq!(next.get(evolved), Err(NodeError::Stale(evolved)));
+    assert!(next.get(new_evolved).is_ok());
+}
+
+#[test]
+fn test_budget() {
+    let rows = soup(0x0f0f_1234_5678_f0f0, 0xffff << 24);
+    let mut unlimited = Universe::<B3S23>::new();
+    let root = build(unlimited.nodes_mut(), &rows, 6, 0, 0);
+    let mut expected = root;
+    for _ in 0..20 {
+        expected = unlimited.evolve(expected, 37).unwrap();
+    }
+
+    for budget in [Budget::Nodes(2000), Budget::Bytes(100_000)] {
+        let mut limited = Universe::<B3S23>::new();
+        limited.set_budget(Some(budget));
+        let mut result = build(limited.nodes_mut(), &rows, 6, 0, 0);
+        for _ in 0..20 {
+            result = limited.evolve(result, 37).unwrap();
+            assert!(!budget.is_exceeded_by(limited.nodes()));
+        }
+        assert_eq!(
+            alive(limited.nodes(), result),
+            alive(unlimited.nodes(), expected)
+        );
     }
 }
@@ -0,0 +1,604 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Reading and writing patterns in the common Life file formats.
+
+mod lif;
+mod macrocell;
+mod plaintext;
+mod rle;
+
+pub use lif::{read_life_105, read_life_106, write_life_105, write_life_106};
+pub use macrocell::{read_macrocell, write_macrocell};
+pub use plaintext::{read_plaintext, write_plaintext};
+pub use rle::{read_rle, write_rle};
+
+use crate::leaf::Cells;
+use crate::life::{NodeArena, NodeError, NodeId};
+use crate::util::Vec2;
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+use std::{fmt, io};
+
+/// Information about a pattern that isn't part of its cells.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Metadata {
+    /// The name of the pattern, from a `#N` line.
+    pub name: Option<String>,
+    /// The author of the pattern, from a `#O` line.
+    pub author: Option<String>,
+    /// Any other comments, from `#C` lines.
+    pub comments: Vec<String>,
+    /// The rule the pattern runs in, such as `B3/S23`.
+    pub rule: Option<String>,
+    /// The number of generations the pattern has been evolved for.
+    pub generation: u64,
+}
+
+/// A pattern file format.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Format {
+    /// The run-length encoded format, with the `.rle` extension.
+    Rle,
+    /// Golly's Macrocell format, with the `.mc` extension.
+    Macrocell,
+    /// The plaintext format, with the `.cells` extension.
+    Plaintext,
+    /// The Life 1.05 format, with the `.lif` or `.life` extension.
+    Life105,
+    /// The Life 1.06 format, which shares its extensions with Life 1.05.
+    Life106,
+}
+
+impl Format {
+    /// Guesses the format of a file from its contents.
+    ///
+    /// Macrocell and Life 1.0x files are recognized by their first line.
+    /// Otherwise, a file whose first line that isn't a `#` comment is an
+    /// `x = ..` header is RLE, and a file starting with a `!` comment or a row
+    /// of `.`, `O` and `*` cells is plaintext. Anything else is assumed to be
+    /// RLE without a header.
+    pub fn detect(input: &[u8]) -> Self {
+        let input = String::from_utf8_lossy(input);
+        let mut lines = input.lines().map(str::trim).filter(|line| !line.is_empty());
+        let first = lines.next().unwrap_or("");
+        if first.starts_with("[M2]") {
+            return Self::Macrocell;
+        }
+        if first.starts_with("#Life 1.05") {
+            return Self::Life105;
+        }
+        if first.starts_with("#Life 1.06") {
+            return Self::Life106;
+        }
+        if first.starts_with('!') {
+            return Self::Plaintext;
+        }
+
+        let data = std::iter::once(first)
+            .chain(lines)
+            .find(|line| !line.starts_with('#'))
+            .unwrap_or("");
+        let is_header = data
+            .strip_prefix('x')
+            .map_or(false, |rest| rest.trim_start().starts_with('='));
+        if !is_header && data.chars().all(|c| matches!(c, '.' | 'O' | '*')) {
+            Self::Plaintext
+        } else {
+            Self::Rle
+        }
+    }
+
+    /// Returns the format for the extension of `path`, if it is known. Both
+    /// Life 1.0x formats use the same extensions, so Life 1.05 is chosen. A
+    /// `.gz` extension is skipped, so `pattern.mc.gz` is a Macrocell file.
+    pub fn from_path(path: impl AsRef<Path>) -> Option<Self> {
+        let mut path = path.as_ref();
+        if is_gzip_path(path) {
+            path = Path::new(path.file_stem()?);
+        }
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "rle" => Some(Self::Rle),
+            "mc" => Some(Self::Macrocell),
+            "cells" => Some(Self::Plaintext),
+            "lif" | "life" => Some(Self::Life105),
+            _ => None,
+        }
+    }
+}
+
+/// The first two bytes of every gzip stream.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Returns `true` if `path` has the `.gz` extension.
+fn is_gzip_path(path: &Path) -> bool {
+    path.extension()
+        .map_or(false, |extension| extension.eq_ignore_ascii_case("gz"))
+}
+
+/// A pattern read from a file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Pattern {
+    /// The root node of the pattern, centered on the origin.
+    pub root: NodeId,
+    /// The name, comments, rule and generation of the pattern.
+    pub metadata: Metadata,
+}
+
+impl Pattern {
+    pub fn new(root: NodeId, metadata: Metadata) -> Self {
+        Self { root, metadata }
+    }
+
+    /// Returns the rule the pattern runs in, defaulting to `B3/S23`.
+    pub fn rule(&self) -> &str {
+        self.metadata.rule.as_deref().unwrap_or("B3/S23")
+    }
+
+    /// Returns the number of generations the pattern has been evolved for.
+    pub fn generation(&self) -> u64 {
+        self.metadata.generation
+    }
+
+    /// Loads the pattern in the file at `path` into `nodes`, detecting its
+    /// format from its contents.
+    pub fn load<T: Default, L: Cells>(
+        path: impl AsRef<Path>,
+        nodes: &mut NodeArena<T, L>,
+    ) -> Result<Self, Error> {
+        Self::read(File::open(path)?, nodes)
+    }
+
+    /// Reads a pattern from `reader` into `nodes`, detecting its format from
+    /// its contents. Gzip-compressed input is recognized by its first bytes and
+    /// decompressed.
+    pub fn read<T: Default, L: Cells>(
+        mut reader: impl Read,
+        nodes: &mut NodeArena<T, L>,
+    ) -> Result<Self, Error> {
+        let mut input = vec![];
+        reader.read_to_end(&mut input)?;
+        if input.starts_with(&GZIP_MAGIC) {
+            let mut decompressed = vec![];
+            GzDecoder::new(input.as_slice()).read_to_end(&mut decompressed)?;
+            input = decompressed;
+        }
+        Self::read_format(input.as_slice(), Format::detect(&input), nodes)
+    }
+
+    /// Reads a pattern in the given format from `reader` into `nodes`.
+    pub fn read_format<T: Default, L: Cells>(
+        reader: impl Read,
+        format: Format,
+        nodes: &mut NodeArena<T, L>,
+    ) -> Result<Self, Error> {
+        let (root, metadata) = match format {
+            Format::Rle => read_rle(reader, nodes)?,
+            Format::Macrocell => read_macrocell(reader, nodes)?,
+            Format::Plaintext => read_plaintext(reader, nodes)?,
+            Format::Life105 => read_life_105(reader, nodes)?,
+            Format::Life106 => read_life_106(reader, nodes)?,
+        };
+        Ok(Self::new(root, metadata))
+    }
+
+    /// Saves the pattern to the file at `path`, choosing the format from its
+    /// extension.
+    pub fn save<T: Default, L: Cells>(
+        &self,
+        path: impl AsRef<Path>,
+        nodes: &NodeArena<T, L>,
+    ) -> Result<(), Error> {
+        let format = Format::from_path(&path).ok_or(Error::UnknownFormat)?;
+        self.save_format(path, format, nodes)
+    }
+
+    /// Saves the pattern to the file at `path` in the given format. The file
+    /// is gzip-compressed if `path` has the `.gz` extension.
+    pub fn save_format<T: Default, L: Cells>(
+        &self,
+        path: impl AsRef<Path>,
+        format: Format,
+        nodes: &NodeArena<T, L>,
+    ) -> Result<(), Error> {
+        let compress = is_gzip_path(path.as_ref());
+        let mut writer = BufWriter::new(File::create(path)?);
+        if compress {
+            let mut encoder = GzEncoder::new(&mut writer, Compression::default());
+            self.write(&mut encoder, format, nodes)?;
+            encoder.finish()?;
+        } else {
+            self.write(&mut writer, format, nodes)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Writes the pattern to `writer` in the given format. Only RLE can hold
+    /// cells in states other than 0 and 1, and the other formats return an
+    /// error for them.
+    pub fn write<T: Default, L: Cells>(
+        &self,
+        writer: impl Write,
+        format: Format,
+        nodes: &NodeArena<T, L>,
+    ) -> Result<(), Error> {
+        let (root, metadata) = (self.root, &self.metadata);
+        match format {
+            Format::Rle => write_rle(writer, nodes, root, metadata),
+            Format::Macrocell => write_macrocell(writer, nodes, root, metadata),
+            Format::Plaintext => write_plaintext(writer, nodes, root, metadata),
+            Format::Life105 => write_life_105(writer, nodes, root, metadata),
+            Format::Life106 => write_life_106(writer, nodes, root, metadata),
+        }
+    }
+}
+
+/// Reads a `#N`, `#O`, `#C` or `#r` comment line into `metadata`. Other kinds
+/// of comment are ignored.
+pub(crate) fn read_comment(line: &str, metadata: &mut Metadata) {
+    let mut chars = line.chars();
+    chars.next();
+    let tag = chars.next();
+    let text = chars.as_str();
+    let text = text.strip_prefix(' ').unwrap_or(text);
+    match tag {
+        Some('N') => metadata.name = Some(text.trim().to_owned()),
+        Some('O') => metadata.author = Some(text.trim().to_owned()),
+        Some('C' | 'c') => metadata.comments.push(text.to_owned()),
+        Some('r') => metadata.rule = Some(text.trim().to_owned()),
+        _ => {}
+    }
+}
+
+/// Writes the `#N`, `#O` and `#C` comment lines for `metadata`.
+pub(crate) fn write_comments(mut writer: impl Write, metadata: &Metadata) -> io::Result<()> {
+    if let Some(name) = &metadata.name {
+        writeln!(writer, "#N {}", name)?;
+    }
+    if let Some(author) = &metadata.author {
+        writeln!(writer, "#O {}", author)?;
+    }
+    for comment in &metadata.comments {
+        writeln!(writer, "#C {}", comment)?;
+    }
+    Ok(())
+}
+
+/// Returns the alive cells of the node `root`, sorted from north to south and
+/// then from west to east, for a `format` that only has two states. Returns an
+/// error if a cell is in any other state.
+pub(crate) fn binary_cells<T: Default, L: Cells>(
+    nodes: &NodeArena<T, L>,
+    root: NodeId,
+    format: Format,
+) -> Result<Vec<Vec2>, Error> {
+    let mut cells = vec![];
+    let mut is_binary = true;
+    nodes.for_each_cell(root, |cell, state| {
+        cells.push(cell);
+        is_binary &= state == 1;
+    })?;
+    if !is_binary {
+        return Err(Error::TooManyStates(format));
+    }
+    cells.sort_by_key(|cell| (-cell.y, cell.x));
+    Ok(cells)
+}
+
+/// Writes `cells`, sorted from north to south and then from west to east, as
+/// rows of `.` and `alive` characters starting at the westernmost cell.
+/// Trailing dead cells are left out of each row, and an empty row is written
+/// as a single `.`.
+pub(crate) fn write_rows(mut writer: impl Write, cells: &[Vec2], alive: char) -> io::Result<()> {
+    let (mut y, min_x) = match cells.first() {
+        Some(first) => (first.y, cells.iter().map(|cell| cell.x).min().unwrap()),
+        None => return Ok(()),
+    };
+    let mut line = String::new();
+    for cell in cells {
+        while cell.y < y {
+            if line.is_empty() {
+                line.push('.');
+            }
+            writeln!(writer, "{}", line)?;
+            line.clear();
+            y -= 1;
+        }
+        let col = (cell.x - min_x) as usize;
+        while line.len() < col {
+            line.push('.');
+        }
+        line.push(alive);
+    }
+    writeln!(writer, "{}", line)
+}
+
+/// An error from reading or writing a pattern.
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// The node to write doesn't exist.
+    Node(NodeError),
+    /// The format to save in can't be chosen from the file extension.
+    UnknownFormat,
+    /// The pattern has cells in states other than 0 and 1, which the format
+    /// can't hold.
+    TooManyStates(Format),
+    /// The input is malformed. Lines and columns are counted from 1.
+    Parse {
+        line: usize,
+        column: usize,
+        kind: ParseError,
+    },
+}
+
+impl Error {
+    pub(crate) fn parse(line: usize, column: usize, kind: ParseError) -> Self {
+        Self::Parse { line, column, kind }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "{}", err),
+            Self::Node(err) => write!(f, "{}", err),
+            Self::UnknownFormat => write!(f, "unknown pattern format"),
+            Self::TooManyStates(format) => {
+                write!(f, "the {:?} format only has two states", format)
+            }
+            Self::Parse { line, column, kind } => {
+                write!(f, "line {}, column {}: {}", line, column, kind)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            Self::Node(err) => Some(err),
+            Self::UnknownFormat | Self::TooManyStates(_) | Self::Parse { .. } => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<NodeError> for Error {
+    fn from(err: NodeError) -> Self {
+        Self::Node(err)
+    }
+}
+
+/// The ways in which a pattern file can be malformed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParseError {
+    /// The `x = .., y = .., rule = ..` header line is malformed.
+    InvalidHeader,
+    /// A character that has no meaning at this point.
+    UnexpectedChar(char),
+    /// A run count that doesn't fit in 64 bits or that moves past the edge
+    /// of the plane.
+    CountOverflow,
+    /// The first line doesn't identify the format of the file.
+    MissingHeader,
+    /// A line of a Macrocell file that is neither a leaf nor a node.
+    InvalidNode,
+    /// A reference to a node that hasn't been defined yet, or that is the
+    /// wrong size.
+    InvalidReference(u64),
+    /// A coordinate that isn't an integer.
+    InvalidCoordinate,
+    /// A cell state that the leaves of the arena can't hold.
+    InvalidState(u16),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::InvalidHeader => write!(f, "invalid header"),
+            Self::UnexpectedChar(c) => write!(f, "unexpected character {:?}", c),
+            Self::CountOverflow => write!(f, "run count is too large"),
+            Self::MissingHeader => write!(f, "missing header"),
+            Self::InvalidNode => write!(f, "invalid node"),
+            Self::InvalidReference(n) => write!(f, "invalid reference to node {}", n),
+            Self::InvalidCoordinate => write!(f, "invalid coordinate"),
+            Self::InvalidState(state) => write!(f, "invalid cell state {}", state),
+        }
+    }
+}
+
+#[test]
+fn test_detect_format() {
+    let assets = [
+        (include_str!("../assets/glider.rle"), Format::Rle),
+        (include_str!("../assets/breeder1.rle"), Format::Rle),
+        (include_str!("../assets/waterbear.mc"), Format::Macrocell),
+        ("!Name: Glider\n.O\n..O\nOOO\n", Format::Plaintext),
+        ("\n.O\n..O\nOOO\n", Format::Plaintext),
+        ("#Life 1.05\n#P -1 -1\n.*\n", Format::Life105),
+        ("#Life 1.06\n0 0\n", Format::Life106),
+        ("#C Headerless\nbo$2bo$3o!", Format::Rle),
+    ];
+    for (input, format) in assets {
+        assert_eq!(Format::detect(input.as_bytes()), format);
+    }
+
+    assert_eq!(Format::from_path("a/b.RLE"), Some(Format::Rle));
+    assert_eq!(Format::from_path("b.mc"), Some(Format::Macrocell));
+    assert_eq!(Format::from_path("b.cells"), Some(Format::Plaintext));
+    assert_eq!(Format::from_path("b.lif"), Some(Format::Life105));
+    assert_eq!(Format::from_path("b.txt"), None);
+    assert_eq!(Format::from_path("b.mc.gz"), Some(Format::Macrocell));
+    assert_eq!(Format::from_path("b.rle.GZ"), Some(Format::Rle));
+    assert_eq!(Format::from_path("b.gz"), None);
+}
+
+#[test]
+fn test_load_and_save() {
+    let mut nodes = NodeArena::<()>::new();
+    let assets = concat!(env!("CARGO_MANIFEST_DIR"), "/assets");
+    let pattern = Pattern::load(format!("{}/waterbear.mc", assets), &mut nodes).unwrap();
+    assert_eq!(pattern.rule(), "B3/S23");
+    assert_eq!(pattern.generation(), 0);
+
+    let glider = Pattern::load(format!("{}/glider.rle", assets), &mut nodes).unwrap();
+    assert_eq!(glider.metadata.name.as_deref(), Some("Glider"));
+
+    let dir = std::env::temp_dir();
+    for (name, format) in [
+        ("glider.rle", Format::Rle),
+        ("glider.mc", Format::Macrocell),
+        ("glider.cells", Format::Plaintext),
+        ("glider.lif", Format::Life105),
+    ] {
+        let path = dir.join(format!("smeagol-{}-{}", std::process::id(), name));
+        glider.save(&path, &nodes).unwrap();
+        let loaded = Pattern::load(&path, &mut nodes).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.root, glider.root, "{:?}", format);
+    }
+
+    let mut output = vec![];
+    glider.write(&mut output, Format::Life106, &nodes).unwrap();
+    let loaded = Pattern::read(output.as_slice(), &mut nodes).unwrap();
+    assert_eq!(loaded.root, glider.root);
+
+    assert!(matches!(
+        glider.save(dir.join("glider.txt"), &nodes),
+        Err(Error::UnknownFormat)
+    ));
+}
+
+#[test]
+fn test_multi_state() {
+    use crate::leaf::MultiLeaf;
+
+    // Two-state patterns can be read into arenas with more states.
+    let mut nodes = NodeArena::<(), MultiLeaf<2>>::new();
+    let glider = include_bytes!("../assets/glider.rle");
+    let glider = Pattern::read(glider.as_slice(), &mut nodes).unwrap();
+    for format in [
+        Format::Rle,
+        Format::Macrocell,
+        Format::Plaintext,
+        Format::Life105,
+        Format::Life106,
+    ] {
+        let mut output = vec![];
+        glider.write(&mut output, format, &nodes).unwrap();
+        let loaded = Pattern::read(output.as_slice(), &mut nodes).unwrap();
+        assert_eq!(loaded.root, glider.root, "{:?}", format);
+    }
+
+    // Only RLE can hold the other states.
+    let input = "x = 3, y = 1, rule = B2/S/C3\nA.B!\n";
+    let pattern = Pattern::read(input.as_bytes(), &mut nodes).unwrap();
+    let mut output = vec![];
+    pattern.write(&mut output, Format::Rle, &nodes).unwrap();
+    assert_eq!(String::from_utf8(output).unwrap(), input);
+    for format in [
+        Format::Macrocell,
+        Format::Plaintext,
+        Format::Life105,
+        Format::Life106,
+    ] {
+        assert!(matches!(
+            pattern.write(vec![], format, &nodes),
+            Err(Error::TooManyStates(f)) if f == format
+        ));
+    }
+}
+
+#[test]
+fn test_topology() {
+    use crate::life::Universe;
+    use crate::rule::split_topology;
+
+    // Rules with Golly's topology suffixes are saved and loaded whole, and
+    // give the same grid again.
+    let mut universe: Universe = Universe::new();
+    let glider = include_bytes!("../assets/glider.rle");
+    let mut glider = Pattern::read(glider.as_slice(), universe.nodes_mut()).unwrap();
+    glider.metadata.rule = Some("B3/S23:T64,64".to_owned());
+    let dir = std::env::temp_dir();
+    for (name, format) in [
+        ("torus.rle", Format::Rle),
+        ("torus.mc", Format::Macrocell),
+        ("torus.lif", Format::Life105),
+    ] {
+        let path = dir.join(format!("smeagol-{}-{}", std::process::id(), name));
+        glider.save(&path, universe.nodes()).unwrap();
+        let loaded = Pattern::load(&path, universe.nodes_mut()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, glider, "{:?}", format);
+    }
+    let mut output = vec![];
+    glider
+        .write(&mut output, Format::Life106, universe.nodes())
+        .unwrap();
+    let loaded = Pattern::read(output.as_slice(), universe.nodes_mut()).unwrap();
+    assert_eq!(loaded, glider);
+
+    // A glider on a torus of 64 by 64 cells is back where it started after
+    // 256 generations.
+    let (rule, topology) = split_topology(loaded.rule()).unwrap();
+    assert_eq!(rule, "B3/S23");
+    universe.set_topology(topology);
+    let result = universe.evolve(loaded.root, 256).unwrap();
+    let mut cells = vec![];
+    universe
+        .nodes()
+        .for_each_alive(result, |cell| cells.push(cell))
+        .unwrap();
+    let mut expected = vec![];
+    universe
+        .nodes()
+        .for_each_alive(glider.root, |cell| expected.push(cell))
+        .unwrap();
+    cells.sort_by_key(|cell| (cell.x, cell.y));
+    expected.sort_by_key(|cell| (cell.x, cell.y));
+    assert_eq!(cells, expected);
+}
+
+#[test]
+fn test_gzip() {
+    let mut nodes = NodeArena::<()>::new();
+    let waterbear = include_bytes!("../assets/waterbear.mc");
+    let pattern = Pattern::read(waterbear.as_slice(), &mut nodes).unwrap();
+
+    // Compressed input is detected from its contents alone.
+    let mut encoder = GzEncoder::new(vec![], Compression::default());
+    encoder.write_all(waterbear).unwrap();
+    let compressed = encoder.finish().unwrap();
+    let loaded = Pattern::read(compressed.as_slice(), &mut nodes).unwrap();
+    assert_eq!(loaded, pattern);
+
+    let glider = include_bytes!("../assets/glider.rle");
+    let glider = Pattern::read(glider.as_slice(), &mut nodes).unwrap();
+    let dir = std::env::temp_dir();
+    for (name, pattern) in [("waterbear.mc.gz", pattern), ("glider.rle.gz", glider)] {
+        let path = dir.join(format!("smeagol-{}-{}", std::process::id(), name));
+        pattern.save(&path, &nodes).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(bytes.starts_with(&GZIP_MAGIC));
+        let loaded = Pattern::read(bytes.as_slice(), &mut nodes).unwrap();
+        assert_eq!(loaded.root, pattern.root);
+    }
+}
@@ -0,0 +1,305 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::leaf::Cells;
+use crate::life::{NodeArena, NodeId};
+use crate::pattern::{
+    binary_cells, read_comment, write_comments, write_rows, Error, Format, Metadata, ParseError,
+};
+use crate::util::Vec2;
+
+use std::io::{Read, Write};
+
+/// Reads the whole of `reader`, checking that its first line starts with
+/// `header`.
+fn read_input(mut reader: impl Read, header: &str) -> Result<String, Error> {
+    let mut input = String::new();
+    reader.read_to_string(&mut input)?;
+    if input.starts_with(header) {
+        Ok(input)
+    } else {
+        Err(Error::parse(1, 1, ParseError::MissingHeader))
+    }
+}
+
+/// Reads a pair of integer coordinates separated by whitespace, starting at
+/// `column`. Returns the column of the first malformed coordinate or of the
+/// first character after the coordinates on failure.
+fn read_coordinates(text: &str, column: usize) -> Result<(i64, i64), (usize, ParseError)> {
+    let field_column = |field: &str| field.as_ptr() as usize - text.as_ptr() as usize + column;
+    let mut fields = text.split_ascii_whitespace();
+    let mut next = || -> Result<i64, (usize, ParseError)> {
+        let field = fields
+            .next()
+            .ok_or((column, ParseError::InvalidCoordinate))?;
+        field
+            .parse()
+            .map_err(|_| (field_column(field), ParseError::InvalidCoordinate))
+    };
+    let coordinates = (next()?, next()?);
+    match fields.next() {
+        Some(extra) => {
+            let c = extra.chars().next().expect("fields aren't empty");
+            Err((field_column(extra), ParseError::UnexpectedChar(c)))
+        }
+        None => Ok(coordinates),
+    }
+}
+
+/// Reads a pattern in the Life 1.05 format into `nodes`.
+///
+/// The cells are given in blocks of `.` and `*` rows, each starting at the
+/// position from the preceding `#P x y` line, where `y` increases to the
+/// south. `#D` lines are comments, a bare `#N` selects the normal Conway rule
+/// and `#R` gives another rule. A `#N` line with text gives the name of the
+/// pattern and an `#O` line gives its author. Returns the root of the
+/// pattern, whose origin is the origin of the file, along with its metadata.
+pub fn read_life_105<T: Default, L: Cells>(
+    reader: impl Read,
+    nodes: &mut NodeArena<T, L>,
+) -> Result<(NodeId, Metadata), Error> {
+    let input = read_input(reader, "#Life 1.05")?;
+
+    let mut metadata = Metadata::default();
+    let mut cells = vec![];
+    let (mut x, mut y) = (0, 0);
+    for (line_index, line) in input.lines().enumerate().skip(1) {
+        let line_number = line_index + 1;
+        if let Some(comment) = line.strip_prefix("#D") {
+            let comment = comment.strip_prefix(' ').unwrap_or(comment);
+            metadata.comments.push(comment.to_owned());
+        } else if line.trim_end() == "#N" {
+            metadata.rule = Some("B3/S23".to_owned());
+        } else if line.starts_with("#N") || line.starts_with("#O") {
+            read_comment(line, &mut metadata);
+        } else if let Some(rule) = line.strip_prefix("#R") {
+            metadata.rule = Some(rule.trim().to_owned());
+        } else if let Some(position) = line.strip_prefix("#P") {
+            (x, y) = read_coordinates(position, 3)
+                .map_err(|(column, kind)| Error::parse(line_number, column, kind))?;
+        } else if !line.starts_with('#') {
+            for (col, c) in line.trim_end().chars().enumerate() {
+                match c {
+                    '.' => {}
+                    '*' => cells.push(Vec2::new(x + col as i64, -y)),
+                    _ => {
+                        let kind = ParseError::UnexpectedChar(c);
+                        return Err(Error::parse(line_number, col + 1, kind));
+                    }
+                }
+            }
+            y += 1;
+        }
+    }
+
+    Ok((nodes.build(cells), metadata))
+}
+
+/// Writes the node `root` in the Life 1.05 format.
+///
+/// The cells are written as a single block. The name from `metadata` is
+/// written as a `#N` line, the author as a `#O` line and the comments as `#D`
+/// lines. The rule is written as a bare `#N` if it is Conway's rule and as
+/// `#R` otherwise. Returns an error if a cell is in a state other than 0 and 1.
+pub fn write_life_105<T: Default, L: Cells>(
+    mut writer: impl Write,
+    nodes: &NodeArena<T, L>,
+    root: NodeId,
+    metadata: &Metadata,
+) -> Result<(), Error> {
+    let cells = binary_cells(nodes, root, Format::Life105)?;
+
+    writeln!(writer, "#Life 1.05")?;
+    if let Some(name) = &metadata.name {
+        writeln!(writer, "#N {}", name)?;
+    }
+    if let Some(author) = &metadata.author {
+        writeln!(writer, "#O {}", author)?;
+    }
+    for comment in &metadata.comments {
+        writeln!(writer, "#D {}", comment)?;
+    }
+    match metadata.rule.as_deref() {
+        Some(rule) if !rule.eq_ignore_ascii_case("B3/S23") => writeln!(writer, "#R {}", rule)?,
+        _ => writeln!(writer, "#N")?,
+    }
+
+    if let Some(first) = cells.first() {
+        let min_x = cells.iter().map(|cell| cell.x).min().unwrap();
+        writeln!(writer, "#P {} {}", min_x, -first.y)?;
+        write_rows(&mut writer, &cells, '*')?;
+    }
+    Ok(())
+}
+
+/// Reads a pattern in the Life 1.06 format into `nodes`.
+///
+/// After the header, each line gives the `x y` coordinates of one alive cell,
+/// where `y` increases to the south. The format has no metadata of its own,
+/// but the `#N`, `#O`, `#C` and `#R` lines written by `write_life_106` are
+/// read back, and other lines starting with `#` are ignored. Returns the root
+/// of the pattern, whose origin is the origin of the file, along with its
+/// metadata.
+pub fn read_life_106<T: Default, L: Cells>(
+    reader: impl Read,
+    nodes: &mut NodeArena<T, L>,
+) -> Result<(NodeId, Metadata), Error> {
+    let input = read_input(reader, "#Life 1.06")?;
+
+    let mut metadata = Metadata::default();
+    let mut cells = vec![];
+    for (line_index, line) in input.lines().enumerate().skip(1) {
+        if let Some(rule) = line.strip_prefix("#R") {
+            metadata.rule = Some(rule.trim().to_owned());
+            continue;
+        }
+        if line.starts_with('#') {
+            read_comment(line, &mut metadata);
+            continue;
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (x, y) = read_coordinates(line, 1)
+            .map_err(|(column, kind)| Error::parse(line_index + 1, column, kind))?;
+        cells.push(Vec2::new(x, -y));
+    }
+
+    Ok((nodes.build(cells), metadata))
+}
+
+/// Writes the node `root` in the Life 1.06 format, one alive cell per line.
+///
+/// The format has no metadata of its own, so the name, author and comments
+/// from `metadata` are written as `#N`, `#O` and `#C` comment lines and the
+/// rule as a `#R` line, which other programs ignore. Returns an error if a
+/// cell is in a state other than 0 and 1.
+pub fn write_life_106<T: Default, L: Cells>(
+    mut writer: impl Write,
+    nodes: &NodeArena<T, L>,
+    root: NodeId,
+    metadata: &Metadata,
+) -> Result<(), Error> {
+    let cells = binary_cells(nodes, root, Format::Life106)?;
+
+    writeln!(writer, "#Life 1.06")?;
+    write_comments(&mut writer, metadata)?;
+    if let Some(rule) = &metadata.rule {
+        writeln!(writer, "#R {}", rule)?;
+    }
+    for cell in cells {
+        writeln!(writer, "{} {}", cell.x, -cell.y)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_life_105() {
+    let mut nodes = NodeArena::<()>::new();
+    let input = "#Life 1.05\n#D Glider\n#N\n#P -1 -1\n.*\n..*\n***\n";
+    let (root, metadata) = read_life_105(input.as_bytes(), &mut nodes).unwrap();
+    assert_eq!(metadata.comments, ["Glider"]);
+    assert_eq!(metadata.rule.as_deref(), Some("B3/S23"));
+
+    let mut output = vec![];
+    write_life_105(&mut output, &nodes, root, &metadata).unwrap();
+    assert_eq!(String::from_utf8(output).unwrap(), input);
+
+    // The same glider in RLE gives the same node.
+    let glider = include_str!("../../assets/glider.rle");
+    let (glider, _) = crate::pattern::read_rle(glider.as_bytes(), &mut nodes).unwrap();
+    assert_eq!(root, glider);
+
+    // Several blocks, and a rule other than Conway's.
+    let input = "#Life 1.05\n#R 23/36\n#P 10 10\n*\n#P -10 -10\n*\n";
+    let (root, metadata) = read_life_105(input.as_bytes(), &mut nodes).unwrap();
+    assert_eq!(metadata.rule.as_deref(), Some("23/36"));
+    let mut cells = vec![];
+    nodes.for_each_alive(root, |cell| cells.push(cell)).unwrap();
+    assert_eq!(cells, [Vec2::new(-10, 10), Vec2::new(10, -10)]);
+
+    // The name and author are kept apart from the comments.
+    let metadata = Metadata {
+        name: Some("Glider".to_owned()),
+        author: Some("Richard K. Guy".to_owned()),
+        ..metadata
+    };
+    let mut output = vec![];
+    write_life_105(&mut output, &nodes, root, &metadata).unwrap();
+    let (_, written_metadata) = read_life_105(output.as_slice(), &mut nodes).unwrap();
+    assert_eq!(written_metadata, metadata);
+
+    let err = read_life_105("#Life 1.05\n#P 1 2 3\n".as_bytes(), &mut nodes).unwrap_err();
+    assert!(matches!(
+        err,
+        Error::Parse {
+            line: 2,
+            column: 8,
+            kind: ParseError::UnexpectedChar('3')
+        }
+    ));
+    let err = read_life_105("#Life 1.05\n#P 1 y\n".as_bytes(), &mut nodes).unwrap_err();
+    assert!(matches!(
+        err,
+        Error::Parse {
+            line: 2,
+            column: 6,
+            kind: ParseError::InvalidCoordinate
+        }
+    ));
+    let err = read_life_105("#Life 1.06\n".as_bytes(), &mut nodes).unwrap_err();
+    assert!(matches!(
+        err,
+        Error::Parse {
+            kind: ParseError::MissingHeader,
+            ..
+        }
+    ));
+}
+
+#[test]
+fn test_life_106() {
+    let mut nodes = NodeArena::<()>::new();
+    let input = "#Life 1.06\n0 -1\n1 0\n-1 1\n0 1\n1 1\n";
+    let (root, _) = read_life_106(input.as_bytes(), &mut nodes).unwrap();
+    let glider = include_str!("../../assets/glider.rle");
+    let (glider, _) = crate::pattern::read_rle(glider.as_bytes(), &mut nodes).unwrap();
+    assert_eq!(root, glider);
+
+    let mut output = vec![];
+    write_life_106(&mut output, &nodes, root, &Metadata::default()).unwrap();
+    assert_eq!(String::from_utf8(output).unwrap(), input);
+
+    // Metadata is kept in comment lines.
+    let metadata = Metadata {
+        name: Some("Glider".to_owned()),
+        author: Some("Richard K. Guy".to_owned()),
+        comments: vec!["The smallest spaceship.".to_owned()],
+        rule: Some("B36/S23".to_owned()),
+        ..Metadata::default()
+    };
+    let mut output = vec![];
+    write_life_106(&mut output, &nodes, root, &metadata).unwrap();
+    let (written, written_metadata) = read_life_106(output.as_slice(), &mut nodes).unwrap();
+    assert_eq!((written, written_metadata), (root, metadata));
+
+    let err = read_life_106("#Life 1.06\n0 0\n1 2 \n3\n".as_bytes(), &mut nodes).unwrap_err();
+    assert!(matches!(
+        err,
+        Error::Parse {
+            line: 4,
+            column: 1,
+            kind: ParseError::InvalidCoordinate
+        }
+    ));
+    let err = read_life_106("#Life 1.06\n0 0\n1 2 3\n".as_bytes(), &mut nodes).unwrap_err();
+    assert!(matches!(
+        err,
+        Error::Parse {
+            line: 3,
+            column: 5,
+            kind: ParseError::UnexpectedChar('3')
+        }
+    ));
+}
@@ -0,0 +1,399 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::leaf::{Cells, Leaf, LeafPart};
+use crate::life::{Node, NodeArena, NodeId};
+use crate::pattern::{read_comment, write_comments, Error, Format, Metadata, ParseError};
+use crate::util::{BitGrid, Grid2, Vec2};
+
+use fnv::FnvHashMap;
+
+use std::io::{self, Read, Write};
+use std::simd::u8x8;
+
+/// The side of a Macrocell leaf is `2^3 = 8`, half the side of a `Leaf`.
+const MC_LEAF_SIDE_LOG2: u8 = 3;
+
+/// A node defined by a line of a Macrocell file.
+#[derive(Clone, Copy)]
+enum McNode {
+    /// An 8 by 8 leaf, which is a quarter of a `Leaf`.
+    Part(LeafPart),
+    /// A node of side 16 or more.
+    Node(NodeId),
+}
+
+/// Reads a pattern in Golly's Macrocell format into `nodes`.
+///
+/// Macrocell files describe a quadtree directly: each line defines a node,
+/// either an 8 by 8 leaf or a branch of side `2^k` that refers to four
+/// previously defined nodes by their line numbers. The branches are rebuilt
+/// directly in `nodes` without ever flattening the pattern into cells.
+/// Returns the root of the pattern, which is the last node in the file,
+/// centered on the origin. Alive cells are in state 1.
+pub fn read_macrocell<T: Default, L: Cells>(
+    mut reader: impl Read,
+    nodes: &mut NodeArena<T, L>,
+) -> Result<(NodeId, Metadata), Error> {
+    let mut input = String::new();
+    reader.read_to_string(&mut input)?;
+
+    let mut lines = input.lines().enumerate().map(|(i, line)| (i + 1, line));
+    match lines.next() {
+        Some((_, line)) if line.starts_with("[M2]") => {}
+        _ => return Err(Error::parse(1, 1, ParseError::MissingHeader)),
+    }
+
+    let mut metadata = Metadata::default();
+    // Node number 0 is the empty node of any size, so the table starts with a
+    // placeholder to keep the numbering the same as in the file.
+    let mut table: Vec<(u8, McNode)> = vec![(0, McNode::Part(LeafPart::empty()))];
+    for (line_number, line) in lines {
+        let error = |column, kind| Error::parse(line_number, column, kind);
+        if let Some(rule) = line.strip_prefix("#R") {
+            metadata.rule = Some(rule.trim().to_owned());
+        } else if let Some(generation) = line.strip_prefix("#G") {
+            metadata.generation = generation
+                .trim()
+                .parse()
+                .map_err(|_| error(1, ParseError::InvalidHeader))?;
+        } else if line.starts_with('#') {
+            read_comment(line, &mut metadata);
+        } else if line.starts_with(|c: char| c.is_ascii_digit()) {
+            let node =
+                read_branch(line, &table, nodes).map_err(|(column, kind)| error(column, kind))?;
+            table.push(node);
+        } else if !line.trim().is_empty() {
+            let part = read_leaf(line).map_err(|(column, kind)| error(column, kind))?;
+            table.push((MC_LEAF_SIDE_LOG2, McNode::Part(part)));
+        }
+    }
+
+    let root = match table.pop() {
+        Some((_, McNode::Node(id))) => id,
+        // A single Macrocell leaf is smaller than a `Leaf`, so rebuild it from
+        // its cells instead.
+        Some((_, McNode::Part(part))) => {
+            let mut cells = vec![];
+            for row in 0..8 {
+                for col in 0..8 {
+                    if part.cells.get(row, col) == Some(true) {
+                        cells.push(Vec2::new(col as i64 - 4, 3 - row as i64));
+                    }
+                }
+            }
+            nodes.build(cells)
+        }
+        None => unreachable!("the table always has a placeholder"),
+    };
+    Ok((root, metadata))
+}
+
+/// Reads a leaf line such as `$$..*$...*$.***$`. Each `$` ends a row, `.` is
+/// a dead cell and `*` is an alive cell. Returns the column of the first
+/// invalid character on failure.
+fn read_leaf(line: &str) -> Result<LeafPart, (usize, ParseError)> {
+    let mut rows = [0u8; 8];
+    let (mut row, mut col) = (0, 0);
+    for (i, c) in line.chars().enumerate() {
+        let column = i + 1;
+        match c {
+            '$' => {
+                row += 1;
+                col = 0;
+                continue;
+            }
+            '.' | '*' if row < 8 && col < 8 => {
+                if c == '*' {
+                    rows[row] |= 0x80 >> col;
+                }
+                col += 1;
+            }
+            '.' | '*' => return Err((column, ParseError::InvalidNode)),
+            _ => return Err((column, ParseError::UnexpectedChar(c))),
+        }
+    }
+    Ok(LeafPart::new(u8x8::from_array(rows)))
+}
+
+/// Reads a branch line such as `5 0 3 0 7`, giving the size of the node and
+/// the numbers of its northwest, northeast, southwest and southeast children.
+/// Returns the column of the first invalid field on failure.
+fn read_branch<T: Default, L: Cells>(
+    line: &str,
+    table: &[(u8, McNode)],
+    nodes: &mut NodeArena<T, L>,
+) -> Result<(u8, McNode), (usize, ParseError)> {
+    let column = |field: &str| field.as_ptr() as usize - line.as_ptr() as usize + 1;
+    let fields: Vec<&str> = line.split_ascii_whitespace().collect();
+    let (side_log2, children) = match fields.as_slice() {
+        [side_log2, children @ ..] if children.len() == 4 => (side_log2, children),
+        _ => return Err((1, ParseError::InvalidNode)),
+    };
+    let side_log2: u8 = side_log2
+        .parse()
+        .ok()
+        .filter(|side_log2| (Leaf::SIDE_LOG2..=Node::MAX_SIDE_LOG2).contains(side_log2))
+        .ok_or((1, ParseError::InvalidNode))?;
+
+    let mut kids = [McNode::Part(LeafPart::empty()); 4];
+    for (kid, &field) in kids.iter_mut().zip(children) {
+        let error = |kind| (column(field), kind);
+        let number: u64 = field.parse().map_err(|_| error(ParseError::InvalidNode))?;
+        *kid = match table.get(number as usize) {
+            _ if number == 0 => {
+                if side_log2 == Leaf::SIDE_LOG2 {
+                    McNode::Part(LeafPart::empty())
+                } else {
+                    McNode::Node(nodes.empty(side_log2 - 1))
+                }
+            }
+            Some(&(child_side_log2, node)) if child_side_log2 + 1 == side_log2 => node,
+            _ => return Err(error(ParseError::InvalidReference(number))),
+        };
+    }
+
+    let kids = Grid2::from_array(kids);
+    let id = if side_log2 == Leaf::SIDE_LOG2 {
+        let parts = kids.map(|kid| match kid {
+            McNode::Part(part) => part,
+            McNode::Node(_) => unreachable!("children of a leaf are parts"),
+        });
+        nodes.insert(Node::Leaf(L::from_binary(Leaf::from_parts(parts))))
+    } else {
+        let ids = kids.map(|kid| match kid {
+            McNode::Node(id) => id,
+            McNode::Part(_) => unreachable!("children of a branch are nodes"),
+        });
+        nodes.make_branch(ids).expect("ids are valid")
+    };
+    Ok((side_log2, McNode::Node(id)))
+}
+
+/// Writes the node `root` in Golly's Macrocell format.
+///
+/// Every distinct node is written once, after its children, so a huge pattern
+/// made of a few repeated pieces gives a small file. Empty nodes are never
+/// written, since node number 0 stands for the empty node of any size. The
+/// rule is written in a `#R` line and the generation in a `#G` line. Returns
+/// an error if a cell is in a state other than 0 and 1.
+pub fn write_macrocell<T: Default, L: Cells>(
+    mut writer: impl Write,
+    nodes: &NodeArena<T, L>,
+    root: NodeId,
+    metadata: &Metadata,
+) -> Result<(), Error> {
+    let side_log2 = nodes.get_node(root)?.side_log2();
+
+    writeln!(writer, "[M2] (smeagol)")?;
+    let rule = metadata.rule.as_deref().unwrap_or("B3/S23");
+    writeln!(writer, "#R {}", rule)?;
+    writeln!(writer, "#G {}", metadata.generation)?;
+    write_comments(&mut writer, metadata)?;
+
+    let mut numbers = McNumbers::default();
+    if write_node(&mut writer, nodes, root, &mut numbers)? == 0 {
+        // The root is the last node in the file, so an empty root still needs
+        // a line of its own.
+        writeln!(writer, "{} 0 0 0 0", side_log2)?;
+    }
+    Ok(())
+}
+
+/// The numbers given to the nodes that have been written so far.
+#[derive(Default)]
+struct McNumbers {
+    parts: FnvHashMap<LeafPart, u64>,
+    nodes: FnvHashMap<NodeId, u64>,
+    count: u64,
+}
+
+impl McNumbers {
+    fn next(&mut self) -> u64 {
+        self.count += 1;
+        self.count
+    }
+}
+
+/// Writes the node `id` and any of its descendants that haven't been written
+/// yet, and returns its number.
+fn write_node<T: Default, L: Cells>(
+    writer: &mut impl Write,
+    nodes: &NodeArena<T, L>,
+    id: NodeId,
+    numbers: &mut McNumbers,
+) -> Result<u64, Error> {
+    let node = nodes.node(id);
+    if node.is_empty() {
+        return Ok(0);
+    }
+    if let Some(&number) = numbers.nodes.get(&id) {
+        return Ok(number);
+    }
+
+    let kids = match node {
+        Node::Leaf(leaf) => leaf
+            .to_binary()
+            .ok_or(Error::TooManyStates(Format::Macrocell))?
+            .to_parts()
+            .try_map(|part| write_part(writer, part, numbers))?,
+        Node::Branch(branch) => branch
+            .children()
+            .try_map(|kid| write_node(writer, nodes, kid, numbers))?,
+    };
+    writeln!(
+        writer,
+        "{} {} {} {} {}",
+        node.side_log2(),
+        kids.nw,
+        kids.ne,
+        kids.sw,
+        kids.se
+    )?;
+    let number = numbers.next();
+    numbers.nodes.insert(id, number);
+    Ok(number)
+}
+
+/// Writes a leaf line for `part` if it hasn't been written yet, and returns
+/// its number.
+fn write_part(writer: &mut impl Write, part: LeafPart, numbers: &mut McNumbers) -> io::Result<u64> {
+    if part.is_empty() {
+        return Ok(0);
+    }
+    if let Some(&number) = numbers.parts.get(&part) {
+        return Ok(number);
+    }
+
+    // Trailing dead cells and empty rows are left out.
+    let rows = part.cells.to_array();
+    let height = rows
+        .iter()
+        .rposition(|&row| row != 0)
+        .map_or(0, |row| row + 1);
+    let mut line = String::new();
+    for &row in &rows[..height] {
+        let width = 8 - row.trailing_zeros() as usize;
+        for col in 0..width {
+            line.push(if row & (0x80 >> col) != 0 { '*' } else { '.' });
+        }
+        line.push('$');
+    }
+    writeln!(writer, "{}", line)?;
+    let number = numbers.next();
+    numbers.parts.insert(part, number);
+    Ok(number)
+}
+
+#[test]
+fn test_read_macrocell() {
+    let mut nodes = NodeArena::<()>::new();
+    let input = "[M2] (test)\n#R B3/S23\n#G 12\n.*$..*$***$\n4 1 0 0 0\n5 0 0 0 2\n";
+    let (root, metadata) = read_macrocell(input.as_bytes(), &mut nodes).unwrap();
+    assert_eq!(metadata.rule.as_deref(), Some("B3/S23"));
+    assert_eq!(metadata.generation, 12);
+    assert_eq!(nodes.get_node(root).unwrap().side_log2(), 5);
+    let mut cells = vec![];
+    nodes.for_each_alive(root, |cell| cells.push(cell)).unwrap();
+    cells.sort_by_key(|cell| (-cell.y, cell.x));
+    assert_eq!(
+        cells,
+        [(1, -1), (2, -2), (0, -3), (1, -3), (2, -3)].map(|(x, y)| Vec2::new(x, y))
+    );
+
+    let waterbear = include_str!("../../assets/waterbear.mc");
+    let (root, metadata) = read_macrocell(waterbear.as_bytes(), &mut nodes).unwrap();
+    assert_eq!(metadata.rule.as_deref(), Some("B3/S23"));
+    assert_eq!(nodes.get_node(root).unwrap().side_log2(), 17);
+    assert!(!nodes.get_node(root).unwrap().is_empty());
+}
+
+#[test]
+fn test_read_macrocell_errors() {
+    let mut nodes = NodeArena::<()>::new();
+    let err = read_macrocell("x = 1, y = 1\no!".as_bytes(), &mut nodes).unwrap_err();
+    assert!(matches!(
+        err,
+        Error::Parse {
+            line: 1,
+            column: 1,
+            kind: ParseError::MissingHeader
+        }
+    ));
+
+    // Node 2 hasn't been defined yet.
+    let input = "[M2]\n.*$\n4 1 0 2 0\n";
+    let err = read_macrocell(input.as_bytes(), &mut nodes).unwrap_err();
+    assert!(matches!(
+        err,
+        Error::Parse {
+            line: 3,
+            column: 7,
+            kind: ParseError::InvalidReference(2)
+        }
+    ));
+
+    // The children of a node of side 32 must have side 16.
+    let input = "[M2]\n.*$\n5 1 0 0 0\n";
+    let err = read_macrocell(input.as_bytes(), &mut nodes).unwrap_err();
+    assert!(matches!(
+        err,
+        Error::Parse {
+            line: 3,
+            column: 3,
+            kind: ParseError::InvalidReference(1)
+        }
+    ));
+
+    let input = "[M2]\n.........*$\n";
+    let err = read_macrocell(input.as_bytes(), &mut nodes).unwrap_err();
+    assert!(matches!(
+        err,
+        Error::Parse {
+            line: 2,
+            column: 9,
+            kind: ParseError::InvalidNode
+        }
+    ));
+}
+
+#[test]
+fn test_write_round_trip() {
+    let mut nodes = NodeArena::<()>::new();
+    let waterbear = include_str!("../../assets/waterbear.mc");
+    let (root, mut metadata) = read_macrocell(waterbear.as_bytes(), &mut nodes).unwrap();
+    metadata.generation = 1234;
+    metadata.comments.push("A comment.".to_owned());
+
+    let mut output = vec![];
+    write_macrocell(&mut output, &nodes, root, &metadata).unwrap();
+    let (written, written_metadata) = read_macrocell(output.as_slice(), &mut nodes).unwrap();
+    assert_eq!(written, root);
+    assert_eq!(written_metadata, metadata);
+    // Golly has already removed the duplicate nodes, so the files have the
+    // same number of nodes.
+    let count_nodes = |text: &str| {
+        text.lines()
+            .filter(|line| !line.starts_with(['[', '#']))
+            .count()
+    };
+    let output = String::from_utf8(output).unwrap();
+    assert_eq!(count_nodes(&output), count_nodes(waterbear));
+
+    // A huge but repetitive pattern has one line per level.
+    let far = 1 << 40;
+    let cells = [Vec2::new(-far, -far), Vec2::new(far - 1, far - 1)];
+    let root = nodes.build(cells);
+    let mut output = vec![];
+    write_macrocell(&mut output, &nodes, root, &Metadata::default()).unwrap();
+    assert!(output.len() < 4096);
+    let (written, _) = read_macrocell(output.as_slice(), &mut nodes).unwrap();
+    assert_eq!(written, root);
+
+    let empty = nodes.empty(6);
+    let mut output = vec![];
+    write_macrocell(&mut output, &nodes, empty, &Metadata::default()).unwrap();
+    let (written, _) = read_macrocell(output.as_slice(), &mut nodes).unwrap();
+    assert_eq!(written, empty);
+}
@@ -0,0 +1,125 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::leaf::Cells;
+use crate::life::{NodeArena, NodeId};
+use crate::pattern::{binary_cells, write_rows, Error, Format, Metadata, ParseError};
+use crate::util::Vec2;
+
+use std::io::{Read, Write};
+
+/// Reads a pattern in the plaintext (`.cells`) format into `nodes`.
+///
+/// Lines starting with `!` are comments. `!Name:` and `!Author:` comments
+/// give the name and author of the pattern, and any other comment is kept in
+/// `Metadata::comments`. Every other line is a row of cells, where `.` is a
+/// dead cell and `O` or `*` is an alive cell. Returns the root of the
+/// pattern, centered on the origin, along with its metadata.
+pub fn read_plaintext<T: Default, L: Cells>(
+    mut reader: impl Read,
+    nodes: &mut NodeArena<T, L>,
+) -> Result<(NodeId, Metadata), Error> {
+    let mut input = String::new();
+    reader.read_to_string(&mut input)?;
+
+    let mut metadata = Metadata::default();
+    let mut cells: Vec<(i64, i64)> = vec![];
+    let (mut height, mut width) = (0i64, 0i64);
+    for (line_index, line) in input.lines().enumerate() {
+        if let Some(comment) = line.strip_prefix('!') {
+            if let Some(name) = comment.strip_prefix("Name:") {
+                metadata.name = Some(name.trim().to_owned());
+            } else if let Some(author) = comment.strip_prefix("Author:") {
+                metadata.author = Some(author.trim().to_owned());
+            } else {
+                metadata.comments.push(comment.to_owned());
+            }
+            continue;
+        }
+
+        let row = height;
+        for (col, c) in line.trim_end().chars().enumerate() {
+            match c {
+                '.' => {}
+                'O' | '*' => cells.push((row, col as i64)),
+                _ => {
+                    let kind = ParseError::UnexpectedChar(c);
+                    return Err(Error::parse(line_index + 1, col + 1, kind));
+                }
+            }
+            width = width.max(col as i64 + 1);
+        }
+        height += 1;
+    }
+
+    let root = nodes.build(
+        cells
+            .into_iter()
+            .map(|(row, col)| Vec2::new(col - width / 2, height / 2 - row)),
+    );
+    Ok((root, metadata))
+}
+
+/// Writes the node `root` in the plaintext (`.cells`) format.
+///
+/// The name, author and comments from `metadata` are written as `!` lines
+/// before the cells. The rule and generation can't be stored in this format,
+/// and neither can cells in states other than 0 and 1.
+pub fn write_plaintext<T: Default, L: Cells>(
+    mut writer: impl Write,
+    nodes: &NodeArena<T, L>,
+    root: NodeId,
+    metadata: &Metadata,
+) -> Result<(), Error> {
+    let cells = binary_cells(nodes, root, Format::Plaintext)?;
+
+    if let Some(name) = &metadata.name {
+        writeln!(writer, "!Name: {}", name)?;
+    }
+    if let Some(author) = &metadata.author {
+        writeln!(writer, "!Author: {}", author)?;
+    }
+    for comment in &metadata.comments {
+        writeln!(writer, "!{}", comment)?;
+    }
+
+    write_rows(&mut writer, &cells, 'O')?;
+    Ok(())
+}
+
+#[test]
+fn test_plaintext_round_trip() {
+    let mut nodes = NodeArena::<()>::new();
+    let input = "!Name: Glider\n!Author: Richard K. Guy\n!The smallest spaceship.\n.O\n..O\nOOO\n";
+    let (root, metadata) = read_plaintext(input.as_bytes(), &mut nodes).unwrap();
+    assert_eq!(metadata.name.as_deref(), Some("Glider"));
+    assert_eq!(metadata.author.as_deref(), Some("Richard K. Guy"));
+    assert_eq!(metadata.comments, ["The smallest spaceship."]);
+
+    let mut output = vec![];
+    write_plaintext(&mut output, &nodes, root, &metadata).unwrap();
+    assert_eq!(String::from_utf8(output).unwrap(), input);
+
+    // The same glider in RLE gives the same node.
+    let glider = include_str!("../../assets/glider.rle");
+    let (glider, _) = crate::pattern::read_rle(glider.as_bytes(), &mut nodes).unwrap();
+    assert_eq!(root, glider);
+
+    // Empty rows are kept.
+    let input = "O\n.\nO\n";
+    let (root, _) = read_plaintext(input.as_bytes(), &mut nodes).unwrap();
+    let mut output = vec![];
+    write_plaintext(&mut output, &nodes, root, &Metadata::default()).unwrap();
+    assert_eq!(String::from_utf8(output).unwrap(), input);
+
+    let err = read_plaintext("!Bad\n.O.\n.Ox\n".as_bytes(), &mut nodes).unwrap_err();
+    assert!(matches!(
+        err,
+        Error::Parse {
+            line: 3,
+            column: 3,
+            kind: ParseError::UnexpectedChar('x')
+        }
+    ));
+}
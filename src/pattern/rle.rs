@@ -0,0 +1,528 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::leaf::Cells;
+use crate::life::{NodeArena, NodeId};
+use crate::pattern::{read_comment, write_comments, Error, Metadata, ParseError};
+use crate::util::Vec2;
+
+use std::io::{Read, Write};
+
+/// The maximum length of a line of cells written by `write_rle`.
+const MAX_LINE_LEN: usize = 70;
+
+/// The number of states that a single letter can stand for. States 1 to 24
+/// are written as `A` to `X`, and each later block of 24 states adds one of
+/// the prefixes `p` to `y`, so state 25 is `pA` and state 255 is `yO`.
+const LETTERS: u8 = 24;
+
+/// The number of cells that `read_rle` collects before adding them to the
+/// pattern, so that huge patterns don't need a list of all their cells.
+const BATCH: usize = 1 << 16;
+
+/// Returns the letters that stand for `state` in a multi-state pattern.
+fn state_letters(state: u8) -> String {
+    let (prefix, letter) = ((state - 1) / LETTERS, (state - 1) % LETTERS);
+    let letter = char::from(b'A' + letter);
+    match prefix {
+        0 => letter.to_string(),
+        _ => format!("{}{}", char::from(b'o' + prefix), letter),
+    }
+}
+
+/// Reads a pattern in the run-length encoded (RLE) format into `nodes`.
+///
+/// Alive cells are written as `o` and dead cells as `b`. Patterns with more
+/// than two states write dead cells as `.` and the other states as letters,
+/// starting with `A` for state 1, and these are read as long as the leaves
+/// of `nodes` can hold the states.
+///
+/// Returns the root of the pattern, centered on the origin, along with its
+/// metadata. Comment lines may only appear before the `x = .., y = ..`
+/// header, and anything after the final `!` is ignored. Runs that go past
+/// the edge of the plane are errors.
+pub fn read_rle<T: Default, L: Cells>(
+    mut reader: impl Read,
+    nodes: &mut NodeArena<T, L>,
+) -> Result<(NodeId, Metadata), Error> {
+    let mut input = String::new();
+    reader.read_to_string(&mut input)?;
+
+    let mut metadata = Metadata::default();
+    let mut size: Option<(i64, i64)> = None;
+    let mut lines = input.lines().enumerate().peekable();
+    while let Some(&(line_index, line)) = lines.peek() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with('#') {
+            read_comment(trimmed, &mut metadata);
+        } else if trimmed.starts_with('x') {
+            let header = read_header(line).map_err(|column| {
+                Error::parse(line_index + 1, column, ParseError::InvalidHeader)
+            })?;
+            size = Some((header.0, header.1));
+            metadata.rule = header.2.or(metadata.rule);
+            lines.next();
+            break;
+        } else if !trimmed.is_empty() {
+            break;
+        }
+        lines.next();
+    }
+
+    // Center the pattern on the origin, using the size from the header if
+    // there is one and reading the cells twice if there isn't.
+    let (width, height) = match size {
+        Some(size) => size,
+        None => read_runs(lines.clone(), L::STATES, |_, _, _, _| {})?,
+    };
+    let mut root = nodes.build([]);
+    let mut cells = vec![];
+    read_runs(lines, L::STATES, |row, col, len, state| {
+        let y = height / 2 - row;
+        for x in col - width / 2..col + len - width / 2 {
+            cells.push((Vec2::new(x, y), state));
+            if cells.len() == BATCH {
+                let batch = nodes.build_states(std::mem::take(&mut cells));
+                root = nodes.union(root, batch).expect("ids are valid");
+            }
+        }
+    })?;
+    let batch = nodes.build_states(cells);
+    let root = nodes.union(root, batch).expect("ids are valid");
+    Ok((root, metadata))
+}
+
+/// Reads the lines of cells of an RLE file, calling `f` with the row, the
+/// column, the length and the state of every run of cells that aren't in
+/// state 0. States must be less than `states`. Returns the width and the
+/// height of the cells.
+fn read_runs<'a>(
+    lines: impl Iterator<Item = (usize, &'a str)>,
+    states: u16,
+    mut f: impl FnMut(i64, i64, i64, u8),
+) -> Result<(i64, i64), Error> {
+    let (mut row, mut col, mut width) = (0i64, 0i64, 0i64);
+    let mut count: Option<u64> = None;
+    let mut prefix: Option<u8> = None;
+
+    'lines: for (line_index, line) in lines {
+        let line_number = line_index + 1;
+        for (column_index, c) in line.chars().enumerate() {
+            let error = |kind| Error::parse(line_number, column_index + 1, kind);
+            if prefix.is_some() && !c.is_ascii_uppercase() {
+                return Err(error(ParseError::UnexpectedChar(c)));
+            }
+            if let Some(digit) = c.to_digit(10) {
+                let n = count.unwrap_or(0);
+                let n = n
+                    .checked_mul(10)
+                    .and_then(|n| n.checked_add(u64::from(digit)))
+                    .ok_or_else(|| error(ParseError::CountOverflow))?;
+                count = Some(n);
+                continue;
+            }
+            if c.is_whitespace() {
+                continue;
+            }
+            if states > 2 && ('p'..='y').contains(&c) {
+                prefix = Some(c as u8 - b'o');
+                continue;
+            }
+            let n = i64::try_from(count.take().unwrap_or(1))
+                .map_err(|_| error(ParseError::CountOverflow))?;
+            let advance = |position: i64| {
+                position
+                    .checked_add(n)
+                    .ok_or_else(|| error(ParseError::CountOverflow))
+            };
+            let state = match c {
+                'o' => Some(1),
+                'A'..='X' => {
+                    let state = u16::from(prefix.take().unwrap_or(0)) * u16::from(LETTERS)
+                        + u16::from(c as u8 - b'A' + 1);
+                    Some(state)
+                }
+                _ => None,
+            };
+            if let Some(state) = state {
+                if state >= states {
+                    return Err(error(ParseError::InvalidState(state)));
+                }
+                let end = advance(col)?;
+                f(row, col, n, state as u8);
+                col = end;
+                width = width.max(col);
+                continue;
+            }
+            match c {
+                'b' | '.' => col = advance(col)?,
+                '$' => {
+                    row = advance(row)?;
+                    col = 0;
+                }
+                '!' => break 'lines,
+                _ => return Err(error(ParseError::UnexpectedChar(c))),
+            }
+            width = width.max(col);
+        }
+    }
+    Ok((width, row.saturating_add(1)))
+}
+
+/// Reads the `x = .., y = .., rule = ..` header line. Returns the width, the
+/// height and the rule, or the column of the first malformed field. The rule
+/// is the rest of the line, since rules such as `B3/S23:T100,80` may contain
+/// commas themselves.
+fn read_header(line: &str) -> Result<(i64, i64, Option<String>), usize> {
+    let (mut width, mut height, mut rule) = (None, None, None);
+    let (mut column, mut start) = (1, 0);
+    for field in line.split(',') {
+        let (key, value) = field.split_once('=').ok_or(column)?;
+        match key.trim() {
+            "x" => width = Some(read_size(value).ok_or(column)?),
+            "y" => height = Some(read_size(value).ok_or(column)?),
+            "rule" => {
+                rule = Some(line[start + key.len() + 1..].trim().to_owned());
+                break;
+            }
+            _ => {}
+        }
+        column += field.chars().count() + 1;
+        start += field.len() + 1;
+    }
+    Ok((width.ok_or(1usize)?, height.ok_or(1usize)?, rule))
+}
+
+/// Reads a width or height from the header, which can't be negative.
+fn read_size(value: &str) -> Option<i64> {
+    value.trim().parse().ok().filter(|&size| size >= 0)
+}
+
+/// Writes the node `root` in the run-length encoded (RLE) format.
+///
+/// The header gives the size of the bounding box of the alive cells and the
+/// rule from `metadata`, defaulting to `B3/S23`. The name, author and
+/// comments from `metadata` are written before the header. Lines of cells
+/// are wrapped so that none is longer than 70 characters. If the leaves of
+/// `nodes` have more than two states, the cells are written with the
+/// multi-state letters described in `read_rle`.
+pub fn write_rle<T: Default, L: Cells>(
+    mut writer: impl Write,
+    nodes: &NodeArena<T, L>,
+    root: NodeId,
+    metadata: &Metadata,
+) -> Result<(), Error> {
+    // Empty nodes are skipped, so this is fast even for huge sparse patterns.
+    let mut cells = vec![];
+    nodes.for_each_cell(root, |cell, state| cells.push((cell, state)))?;
+    cells.sort_by_key(|(cell, _)| (-cell.y, cell.x));
+    let is_multi_state = L::STATES > 2;
+    let dead = if is_multi_state { "." } else { "b" };
+    let tag = |state| match is_multi_state {
+        true => state_letters(state),
+        false => "o".to_owned(),
+    };
+
+    write_comments(&mut writer, metadata)?;
+
+    let (min_x, max_x) = cells
+        .iter()
+        .fold((i64::MAX, i64::MIN), |(min, max), (cell, _)| {
+            (min.min(cell.x), max.max(cell.x))
+        });
+    let (top, bottom) = match (cells.first(), cells.last()) {
+        (Some((first, _)), Some((last, _))) => (first.y, last.y),
+        _ => (0, 1),
+    };
+    let (width, height) = if cells.is_empty() {
+        (0, 0)
+    } else {
+        (max_x - min_x + 1, top - bottom + 1)
+    };
+    let rule = metadata.rule.as_deref().unwrap_or("B3/S23");
+    writeln!(writer, "x = {}, y = {}, rule = {}", width, height, rule)?;
+
+    let mut line = String::new();
+    let mut push = |writer: &mut dyn Write, count: i64, tag: &str| -> Result<(), Error> {
+        let item = match count {
+            1 => tag.to_owned(),
+            _ => format!("{}{}", count, tag),
+        };
+        if line.len() + item.len() > MAX_LINE_LEN {
+            writeln!(writer, "{}", line)?;
+            line.clear();
+        }
+        line.push_str(&item);
+        Ok(())
+    };
+
+    let (mut row, mut col) = (0, 0);
+    let mut run: Option<(i64, i64, u8)> = None;
+    for (cell, state) in cells {
+        let (cell_row, cell_col) = (top - cell.y, cell.x - min_x);
+        match run {
+            // Extend the current run of cells in the same state.
+            Some((start, len, run_state))
+                if cell_row == row && cell_col == start + len && state == run_state =>
+            {
+                run = Some((start, len + 1, state));
+                continue;
+            }
+            Some((_, len, run_state)) => {
+                push(&mut writer, len, &tag(run_state))?;
+                col += len;
+            }
+            None => {}
+        }
+        if cell_row > row {
+            push(&mut writer, cell_row - row, "$")?;
+            row = cell_row;
+            col = 0;
+        }
+        if cell_col > col {
+            push(&mut writer, cell_col - col, dead)?;
+            col = cell_col;
+        }
+        run = Some((cell_col, 1, state));
+    }
+    if let Some((_, len, state)) = run {
+        push(&mut writer, len, &tag(state))?;
+    }
+    push(&mut writer, 1, "!")?;
+    writeln!(writer, "{}", line)?;
+    Ok(())
+}
+
+#[test]
+fn test_read_assets() {
+    use crate::life::Memo;
+
+    let mut nodes = NodeArena::<Memo>::new();
+    let population = |nodes: &NodeArena<Memo>, root| {
+        let mut population = 0;
+        nodes.for_each_alive(root, |_| population += 1).unwrap();
+        population
+    };
+
+    let glider = include_str!("../../assets/glider.rle");
+    let (root, metadata) = read_rle(glider.as_bytes(), &mut nodes).unwrap();
+    let mut cells = vec![];
+    nodes.for_each_alive(root, |cell| cells.push(cell)).unwrap();
+    cells.sort_by_key(|cell| (-cell.y, cell.x));
+    assert_eq!(
+        cells,
+        [(0, 1), (1, 0), (-1, -1), (0, -1), (1, -1)].map(|(x, y)| Vec2::new(x, y))
+    );
+    assert_eq!(metadata.name.as_deref(), Some("Glider"));
+    assert_eq!(metadata.author.as_deref(), Some("Richard K. Guy"));
+    assert_eq!(metadata.comments.len(), 2);
+    assert_eq!(metadata.rule.as_deref(), Some("B3/S23"));
+
+    let gun = include_str!("../../assets/gosperglidergun.rle");
+    let (root, _) = read_rle(gun.as_bytes(), &mut nodes).unwrap();
+    assert_eq!(population(&nodes, root), 36);
+
+    let pulsar = include_str!("../../assets/pulsar.rle");
+    let (root, _) = read_rle(pulsar.as_bytes(), &mut nodes).unwrap();
+    assert_eq!(population(&nodes, root), 48);
+
+    let breeder = include_str!("../../assets/breeder1.rle");
+    let (_, metadata) = read_rle(breeder.as_bytes(), &mut nodes).unwrap();
+    assert_eq!(metadata.comments[1], " in the early 1970s.");
+    assert_eq!(metadata.rule.as_deref(), Some("b3/s23"));
+}
+
+#[test]
+fn test_read_errors() {
+    let mut nodes = NodeArena::<()>::new();
+
+    // Runs may be split across lines.
+    let (root, _) = read_rle("x = 4, y = 2\n2\no$\n\n4o!".as_bytes(), &mut nodes).unwrap();
+    let mut population = 0;
+    nodes.for_each_alive(root, |_| population += 1).unwrap();
+    assert_eq!(population, 6);
+
+    // Long runs are added to the pattern in batches.
+    let (root, _) = read_rle("200000o$o!".as_bytes(), &mut nodes).unwrap();
+    let mut population = 0;
+    nodes.for_each_alive(root, |_| population += 1).unwrap();
+    assert_eq!(population, 200001);
+
+    let err = read_rle("#N Bad\nx = 3, y = 3\nbob$2bo$3q!".as_bytes(), &mut nodes).unwrap_err();
+    assert!(matches!(
+        err,
+        Error::Parse {
+            line: 3,
+            column: 10,
+            kind: ParseError::UnexpectedChar('q')
+        }
+    ));
+
+    let err = read_rle("x = 3, y = three\nbo!".as_bytes(), &mut nodes).unwrap_err();
+    assert!(matches!(
+        err,
+        Error::Parse {
+            line: 1,
+            column: 7,
+            kind: ParseError::InvalidHeader
+        }
+    ));
+    let err = read_rle("x = -3, y = 1\nbo!".as_bytes(), &mut nodes).unwrap_err();
+    assert!(matches!(
+        err,
+        Error::Parse {
+            line: 1,
+            column: 1,
+            kind: ParseError::InvalidHeader
+        }
+    ));
+
+    // Run counts that overflow or that move past the edge of the plane are
+    // rejected.
+    for input in [
+        "99999999999999999999o!",
+        "9223372036854775808b!",
+        "9223372036854775807b9223372036854775807b!",
+        "9223372036854775807$9223372036854775807$o!",
+    ] {
+        let err = read_rle(input.as_bytes(), &mut nodes).unwrap_err();
+        assert!(
+            matches!(
+                err,
+                Error::Parse {
+                    kind: ParseError::CountOverflow,
+                    ..
+                }
+            ),
+            "{}",
+            input
+        );
+    }
+}
+
+#[test]
+fn test_write_round_trip() {
+    let assets = [
+        include_str!("../../assets/breeder1.rle"),
+        include_str!("../../assets/figureeight.rle"),
+        include_str!("../../assets/glider.rle"),
+        include_str!("../../assets/gosperglidergun.rle"),
+        include_str!("../../assets/pentadecathlon.rle"),
+        include_str!("../../assets/pulsar.rle"),
+        include_str!("../../assets/queenbeeshuttle.rle"),
+        include_str!("../../assets/sirrobin.rle"),
+        include_str!("../../assets/spaghettimonster.rle"),
+        include_str!("../../assets/weekender.rle"),
+    ];
+    let mut nodes = NodeArena::<()>::new();
+    let normalized_cells = |nodes: &NodeArena<()>, root| {
+        let mut cells = vec![];
+        nodes.for_each_alive(root, |cell| cells.push(cell)).unwrap();
+        let min_x = cells.iter().map(|cell| cell.x).min().unwrap();
+        let max_y = cells.iter().map(|cell| cell.y).max().unwrap();
+        let mut cells: Vec<_> = cells
+            .into_iter()
+            .map(|cell| (max_y - cell.y, cell.x - min_x))
+            .collect();
+        cells.sort_unstable();
+        cells
+    };
+
+    for asset in assets {
+        let (root, metadata) = read_rle(asset.as_bytes(), &mut nodes).unwrap();
+        let mut output = vec![];
+        write_rle(&mut output, &nodes, root, &metadata).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output
+            .lines()
+            .all(|line| line.starts_with('#') || line.len() <= 70));
+
+        let (written, written_metadata) = read_rle(output.as_bytes(), &mut nodes).unwrap();
+        assert_eq!(written_metadata, metadata);
+        assert_eq!(
+            normalized_cells(&nodes, written),
+            normalized_cells(&nodes, root)
+        );
+    }
+
+    // Two gliders far apart are written without visiting the space between.
+    let far = 1 << 40;
+    let root = nodes.build([Vec2::new(0, 0), Vec2::new(far, -far)]);
+    let mut output = vec![];
+    write_rle(&mut output, &nodes, root, &Metadata::default()).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        format!(
+            "x = {}, y = {}, rule = B3/S23\no{}${}bo!\n",
+            far + 1,
+            far + 1,
+            far,
+            far
+        )
+    );
+
+    // Rules may contain commas, and are read back whole.
+    for rule in [
+        "B3/S23:T100,80",
+        "R5,C0,M1,S34..58,B34..45,NM",
+        "MS,D0;8;4;3;2;5;9;7;1;6;10;11;12;13;14;15",
+        "R2,W20T",
+    ] {
+        let metadata = Metadata {
+            rule: Some(rule.to_owned()),
+            ..Metadata::default()
+        };
+        let mut output = vec![];
+        write_rle(&mut output, &nodes, root, &metadata).unwrap();
+        let (written, written_metadata) = read_rle(output.as_slice(), &mut nodes).unwrap();
+        assert_eq!(written_metadata, metadata);
+        assert_eq!(
+            normalized_cells(&nodes, written),
+            normalized_cells(&nodes, root)
+        );
+    }
+}
+
+#[test]
+fn test_multi_state() {
+    use crate::leaf::MultiLeaf;
+
+    let mut nodes = NodeArena::<(), MultiLeaf<8>>::new();
+    let input = "x = 4, y = 2, rule = B2/S345/C4\n.AB$C2.pA!\n";
+    let (root, metadata) = read_rle(input.as_bytes(), &mut nodes).unwrap();
+    let mut cells = vec![];
+    nodes
+        .for_each_cell(root, |cell, state| cells.push((cell, state)))
+        .unwrap();
+    cells.sort_by_key(|(cell, _)| (-cell.y, cell.x));
+    assert_eq!(
+        cells,
+        [(-1, 1, 1), (0, 1, 2), (-2, 0, 3), (1, 0, 25)].map(|(x, y, s)| (Vec2::new(x, y), s))
+    );
+
+    let mut output = vec![];
+    write_rle(&mut output, &nodes, root, &metadata).unwrap();
+    assert_eq!(String::from_utf8(output).unwrap(), input);
+    assert_eq!(state_letters(255), "yO");
+
+    // Two-state arenas only take state 1.
+    let mut nodes = NodeArena::<()>::new();
+    let err = read_rle("x = 2, y = 1\nAB!".as_bytes(), &mut nodes).unwrap_err();
+    assert!(matches!(
+        err,
+        Error::Parse {
+            line: 2,
+            column: 2,
+            kind: ParseError::InvalidState(2)
+        }
+    ));
+    let err = read_rle("x = 2, y = 1\npA!".as_bytes(), &mut nodes).unwrap_err();
+    assert!(matches!(
+        err,
+        Error::Parse {
+            kind: ParseError::UnexpectedChar('p'),
+            ..
+        }
+    ));
+}
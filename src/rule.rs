@@ -0,0 +1,332 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Rules other than Conway's, parsed from the usual rule strings.
+
+mod generations;
+mod isotropic;
+mod larger_than_life;
+mod map;
+mod margolus;
+mod table;
+mod totalistic;
+mod transitions;
+mod tree;
+mod wolfram;
+
+pub use generations::Generations;
+pub use isotropic::Isotropic;
+pub use larger_than_life::LargerThanLife;
+pub use map::MapRule;
+pub use margolus::Margolus;
+pub use totalistic::{Neighborhood, Totalistic};
+pub use tree::RuleTree;
+pub use wolfram::Wolfram;
+
+use crate::topology::Topology;
+use crate::util::{BitGrid, Dir};
+
+use std::fmt;
+
+/// Splits a rule string into its birth and survival parts. The rule may be
+/// written as `B3/S23`, with or without the slash, in either case and with
+/// either part first, or as `23/3` with the survival part first.
+pub(crate) fn split_rule(rule: &str) -> Result<(&str, &str), RuleError> {
+    let rule = rule.trim();
+    if rule.starts_with(|c: char| c.is_ascii_digit() || c == '/') {
+        let (survival, birth) = rule.split_once('/').ok_or(RuleError::InvalidFormat)?;
+        return Ok((birth, survival));
+    }
+
+    let split = rule
+        .get(1..)
+        .and_then(|rest| rest.find(['B', 'b', 'S', 's']))
+        .ok_or(RuleError::InvalidFormat)?
+        + 1;
+    let (first, second) = rule.split_at(split);
+    let first = first.strip_suffix('/').unwrap_or(first);
+    match (first.split_at(1), second.split_at(1)) {
+        (("B" | "b", birth), ("S" | "s", survival)) => Ok((birth, survival)),
+        (("S" | "s", survival), ("B" | "b", birth)) => Ok((birth, survival)),
+        _ => Err(RuleError::InvalidFormat),
+    }
+}
+
+/// Splits the topology suffix that Golly adds to rule strings, such as
+/// `:T100,80` in `B3/S23:T100,80`, off a rule string. Returns the rule and
+/// the topology, which is the infinite plane if there is no suffix.
+pub fn split_topology(rule: &str) -> Result<(&str, Topology), RuleError> {
+    match rule.split_once(':') {
+        Some((rule, topology)) => Ok((rule.trim(), topology.parse()?)),
+        None => Ok((rule.trim(), Topology::Plane)),
+    }
+}
+
+/// Adds three bit grids, returning the sum and carry bits.
+fn full_add<B: BitGrid>(a: B, b: B, c: B) -> (B, B) {
+    let ab = a ^ b;
+    (ab ^ c, (a & b) | (ab & c))
+}
+
+/// Counts the alive Moore neighbors of every cell, returning the four bits of
+/// the count from least to most significant.
+///
+/// This is the same bit-sliced adder as `B3S23::tick`: the three cells of each
+/// row are summed first, and then the rows are summed.
+pub(crate) fn moore_counts<B: BitGrid>(a: B) -> [B; 4] {
+    // The sum of the west and east neighbors, and of those and the cell itself.
+    let (aw, ae) = (a.shift(Dir::West), a.shift(Dir::East));
+    let (s0, s1) = (aw ^ ae, aw & ae);
+    let (hs0, hs1) = (s0 ^ a, (s0 & a) | s1);
+    // Add the sums of the rows to the north and south to the first sum.
+    let (n0, n1) = (hs0.shift(Dir::North), hs1.shift(Dir::North));
+    let (so0, so1) = (hs0.shift(Dir::South), hs1.shift(Dir::South));
+    let (c0, k0) = full_add(s0, n0, so0);
+    let (t1, k1) = full_add(s1, n1, so1);
+    let (c1, k2) = (t1 ^ k0, t1 & k0);
+    [c0, c1, k1 ^ k2, k1 & k2]
+}
+
+/// Counts the alive cells among `neighbors`, which are the grid shifted so
+/// that each neighbor of a cell is in its place. Returns the four bits of the
+/// count from least to most significant, so there may be at most 15
+/// neighbors.
+pub(crate) fn neighbor_counts<B: BitGrid>(neighbors: &[B]) -> [B; 4] {
+    let zero = neighbors[0] & !neighbors[0];
+    let mut count = [zero; 4];
+    for &neighbor in neighbors {
+        // A bit-sliced increment of the count wherever the neighbor is alive.
+        let mut carry = neighbor;
+        for bit in &mut count {
+            (*bit, carry) = (*bit ^ carry, *bit & carry);
+        }
+    }
+    count
+}
+
+/// Returns the cells whose bit-sliced `count` is equal to `n`.
+pub(crate) fn count_equals<B: BitGrid>(count: [B; 4], n: u8) -> B {
+    let bits = count
+        .zip([0, 1, 2, 3])
+        .map(|(bit, i)| if n >> i & 1 == 1 { bit } else { !bit });
+    bits[0] & bits[1] & bits[2] & bits[3]
+}
+
+/// An error from parsing a rule string.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RuleError {
+    /// A character that has no meaning at this point.
+    UnexpectedChar(char),
+    /// The rule string isn't in any of the accepted forms.
+    InvalidFormat,
+    /// The data of a `MAP` rule has the wrong number of characters.
+    InvalidLength(usize),
+    /// A Generations rule with too few or too many states.
+    InvalidStates(u16),
+    /// A rule with `B0` where that isn't supported.
+    UnsupportedB0,
+    /// A line of a rule file, counting from 1, that can't be read.
+    InvalidLine(usize),
+}
+
+impl fmt::Display for RuleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnexpectedChar(c) => write!(f, "unexpected character {:?} in rule", c),
+            Self::InvalidFormat => write!(f, "invalid rule"),
+            Self::InvalidLength(len) => write!(f, "MAP data has the wrong length {}", len),
+            Self::InvalidStates(states) => write!(f, "invalid number of states {}", states),
+            Self::UnsupportedB0 => write!(f, "B0 isn't supported in this rule"),
+            Self::InvalidLine(line) => write!(f, "invalid line {} in rule file", line),
+        }
+    }
+}
+
+impl std::error::Error for RuleError {}
+
+#[test]
+fn test_split_topology() {
+    assert_eq!(
+        split_topology("B3/S23:T100,80"),
+        Ok((
+            "B3/S23",
+            Topology::Torus {
+                width: 100,
+                height: 80
+            }
+        ))
+    );
+    assert_eq!(split_topology("B3/S23"), Ok(("B3/S23", Topology::Plane)));
+    assert_eq!(split_topology("B3/S23:"), Err(RuleError::InvalidFormat));
+    let (rule, topology) = split_topology("B2/S/C3:K40,30*").unwrap();
+    assert_eq!(
+        rule.parse(),
+        Ok(Generations::<8>::new(Totalistic::new(&[2], &[]), 3))
+    );
+    assert_eq!(topology.to_string(), "K40,30*");
+}
+
+/// Advances `rows` by one generation the slow way, one cell at a time, with
+/// `alive` deciding the next state of a cell from its state and its Moore
+/// neighbors. The rows wrap around and the columns don't, like `BitGrid`.
+#[cfg(test)]
+pub(crate) fn tick_slowly(
+    rows: [u64; 64],
+    alive: impl Fn(bool, [[bool; 3]; 3]) -> bool,
+) -> [u64; 64] {
+    let get = |row: i64, col: i64| {
+        (0..64).contains(&col) && rows[row.rem_euclid(64) as usize] >> (63 - col) & 1 == 1
+    };
+    let mut next = [0; 64];
+    for (row, next) in next.iter_mut().enumerate() {
+        for col in 0..64 {
+            let (row, col) = (row as i64, col as i64);
+            let window = [-1, 0, 1].map(|dy| [-1, 0, 1].map(|dx| get(row + dy, col + dx)));
+            if alive(get(row, col), window) {
+                *next |= 1 << (63 - col);
+            }
+        }
+    }
+    next
+}
+
+/// Checks that `universe` evolves a random soup the same way as a slow
+/// simulation that goes one cell at a time, with `next` giving the next state
+/// of a cell from the generation, the cell and a function that looks up the
+/// state of the cell `dx` cells east and `dy` cells north of it.
+///
+/// On the plane the soup is a square of 24 by 24 cells in the middle of a
+/// grid of `side` by `side` cells, whose edges nothing may reach. On a
+/// bounded grid the soup fills the whole grid and `side` is ignored. Every
+/// cell of the soup is in one of `states`, picked at random.
+#[cfg(test)]
+pub(crate) fn check_evolve<R: crate::life::Rule>(
+    universe: &mut crate::life::Universe<R>,
+    side: usize,
+    states: [u8; 4],
+    seed: u64,
+    ticks: u64,
+    next: impl Fn(u64, crate::util::Vec2, &dyn Fn(i64, i64) -> u8) -> u8,
+) {
+    use crate::life::{soup, Universe};
+    use crate::util::Vec2;
+
+    let topology = universe.topology();
+    let (northwest, southeast) = topology.bounds().unwrap_or_else(|| {
+        let half = side as i64 / 2;
+        (Vec2::new(-half, half - 1), Vec2::new(half - 1, -half))
+    });
+    let width = (southeast.x - northwest.x + 1) as usize;
+    let height = (northwest.y - southeast.y + 1) as usize;
+    let position = |row: usize, col: usize| northwest + Vec2::new(col as i64, -(row as i64));
+
+    let (top, left, rows, cols) = match topology.size() {
+        Some(_) => (0, 0, height, width),
+        None => ((height - 24) / 2, (width - 24) / 2, 24, 24),
+    };
+    assert!(
+        rows <= 64 && cols <= 64,
+        "{} is too big for a soup",
+        topology
+    );
+    let (low, high) = (soup(seed, !0), soup(seed + 1, !0));
+    let mut grid = vec![vec![0u8; width]; height];
+    for row in 0..rows {
+        for col in 0..cols {
+            let bits = (low[row] >> col & 1) | (high[row] >> col & 1) << 1;
+            grid[top + row][left + col] = states[bits as usize];
+        }
+    }
+    // While the background is alive the nodes hold the complement of the
+    // cells.
+    let to_cells = |grid: &[Vec<u8>], background: u8| {
+        let mut cells = vec![];
+        for (row, states) in grid.iter().enumerate() {
+            for (col, &state) in states.iter().enumerate() {
+                if state != background {
+                    cells.push((position(row, col), state ^ background));
+                }
+            }
+        }
+        cells
+    };
+
+    let root = universe.nodes_mut().build_states(to_cells(&grid, 0));
+    let mut background = false;
+    let mut expected = vec![to_cells(&grid, 0)];
+    for generation in 0..ticks {
+        // Cells beyond the edges of the grid are the background.
+        let outside = u8::from(background);
+        let get = |cell: Vec2| {
+            topology
+                .wrap(cell)
+                .and_then(|cell| {
+                    let row = usize::try_from(northwest.y - cell.y).ok()?;
+                    let col = usize::try_from(cell.x - northwest.x).ok()?;
+                    grid.get(row)?.get(col).copied()
+                })
+                .unwrap_or(outside)
+        };
+        let new_grid: Vec<Vec<u8>> = (0..height)
+            .map(|row| {
+                (0..width)
+                    .map(|col| {
+                        let cell = position(row, col);
+                        next(generation, cell, &|dx, dy| get(cell + Vec2::new(dx, dy)))
+                    })
+                    .collect()
+            })
+            .collect();
+        background = universe.rule().next_background(background);
+        if topology.size().is_none() {
+            let mut edges = [&new_grid[0], &new_grid[height - 1]]
+                .into_iter()
+                .flatten()
+                .chain(new_grid.iter().flat_map(|row| [&row[0], &row[width - 1]]));
+            assert!(
+                edges.all(|&state| state == u8::from(background)),
+                "the soup reaches the edges after {} generations",
+                generation + 1
+            );
+        }
+        grid = new_grid;
+        expected.push(to_cells(&grid, u8::from(background)));
+    }
+    assert!(!expected[ticks as usize].is_empty(), "the soup dies out");
+
+    let cells = |universe: &Universe<R>, id| {
+        let mut cells = vec![];
+        universe
+            .nodes()
+            .for_each_cell(id, |cell, state| cells.push((cell, state)))
+            .unwrap();
+        cells.sort_by_key(|&(cell, _)| (-cell.y, cell.x));
+        cells
+    };
+    let result = universe.evolve(root, ticks).unwrap();
+    assert_eq!(
+        cells(universe, result),
+        expected[ticks as usize],
+        "{} after {} generations",
+        topology,
+        ticks
+    );
+    // Steps of other sizes, which start on both even and odd generations.
+    let (mut id, mut generation) = (root, 0);
+    for step in [1, 3, 5, 2, 13, 7].into_iter().cycle() {
+        let step = step.min(ticks - generation);
+        if step == 0 {
+            break;
+        }
+        id = universe.evolve_from(id, generation, step).unwrap();
+        generation += step;
+        assert_eq!(
+            cells(universe, id),
+            expected[generation as usize],
+            "{} after {} generations",
+            topology,
+            generation
+        );
+    }
+}
@@ -0,0 +1,235 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::leaf::{Leaf, MultiLeaf};
+use crate::life::{LifeRule, Phase, Rule};
+use crate::rule::{Neighborhood, RuleError, Totalistic};
+use crate::util::{BitGrid, Grid2};
+
+use std::fmt;
+use std::str::FromStr;
+
+/// A Generations rule, where alive cells that don't survive take several
+/// generations to die.
+///
+/// State 0 is dead and state 1 is alive. Dead cells are born and alive cells
+/// survive as in an outer-totalistic rule, counting only the alive neighbors.
+/// An alive cell that doesn't survive moves to state 2, and each later state
+/// moves to the next one every generation until it wraps around to 0.
+///
+/// The states are stored in `N` bit-planes, so a rule can have at most `2^N`
+/// states, and never more than 256. Parses from the `B2/S/C3` notation, where
+/// `C` or `G` gives the number of states, and from the older `345/2/4`
+/// notation that gives the survival counts first. Either may end with the
+/// `V` or `H` suffix of a `Totalistic` rule. Rules with `B0` aren't
+/// supported.
+///
+/// `N` defaults to 8 so that any rule can be parsed, but every leaf then
+/// stores 8 planes whatever the number of states. Brian's Brain (`B2/S/C3`)
+/// only needs 2, and the other 6 are always empty yet still hashed and kept
+/// in memory for every node. Patterns run faster with the smallest `N` that
+/// holds the states, such as `Generations<2>` for rules with up to 4 states.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Generations<const N: usize = 8> {
+    life: Totalistic,
+    states: u16,
+}
+
+impl<const N: usize> Generations<N> {
+    /// Creates the rule where cells are born and survive as in `life`, with
+    /// `states` states in total.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `life` has `B0`, or if `states` is less than 2 or more than
+    /// the bit-planes can hold.
+    pub fn new(life: Totalistic, states: u16) -> Self {
+        assert!(!life.is_birth(0), "B0 isn't supported");
+        assert!(
+            Self::is_valid_states(states),
+            "{} states are out of range",
+            states
+        );
+        Self { life, states }
+    }
+
+    fn is_valid_states(states: u16) -> bool {
+        (2..=256).contains(&states) && u32::from(states) <= 1 << N
+    }
+
+    /// Returns the outer-totalistic rule for births and survivals.
+    pub fn totalistic(&self) -> Totalistic {
+        self.life
+    }
+
+    pub fn states(&self) -> u16 {
+        self.states
+    }
+}
+
+impl<const N: usize> Generations<N> {
+    /// Advances cells stored as bit-planes by one generation.
+    fn tick<B: BitGrid>(&self, planes: [B; N]) -> [B; N] {
+        let zero = planes[0] & !planes[0];
+        let nonzero = planes.iter().fold(zero, |nonzero, &p| nonzero | p);
+        let alive = planes[1..].iter().fold(planes[0], |alive, &p| alive & !p);
+        // Only dead and alive cells can become or stay alive.
+        let dying = nonzero & !alive;
+        let next_alive = self.life.tick(alive) & !dying;
+
+        // Every other nonzero cell moves to the next state, which is a
+        // bit-sliced increment.
+        let ageing = nonzero & !next_alive;
+        let mut carry = ageing;
+        let mut next = planes.map(|plane| {
+            let plane = plane & ageing;
+            let sum = plane ^ carry;
+            carry = carry & plane;
+            sum
+        });
+        // The last state wraps around to 0. With `2^N` states the carry out of
+        // the last plane already does that.
+        if u32::from(self.states) < 1 << N {
+            let mut wraps = !zero;
+            for (i, &plane) in next.iter().enumerate() {
+                let bit = if self.states >> i & 1 == 1 {
+                    plane
+                } else {
+                    !plane
+                };
+                wraps = wraps & bit;
+            }
+            next = next.map(|plane| plane & !wraps);
+        }
+        next[0] = next[0] | next_alive;
+        next
+    }
+}
+
+impl<const N: usize> Rule for Generations<N> {
+    type Leaf = MultiLeaf<N>;
+
+    fn next_background(&self, _: bool) -> bool {
+        // Without `B0` the background stays dead.
+        false
+    }
+
+    fn tick_leaf(&self, leaf: MultiLeaf<N>, _: Phase, _: Phase) -> MultiLeaf<N> {
+        MultiLeaf::new(self.tick(leaf.planes))
+    }
+
+    fn radius(&self) -> u8 {
+        LifeRule::radius(&self.life)
+    }
+
+    fn step_square(&self, leaves: Grid2<MultiLeaf<N>>, _: Phase, ticks: u8) -> MultiLeaf<N> {
+        let mut planes = [(); N].map(|_| Default::default());
+        for (i, plane) in planes.iter_mut().enumerate() {
+            *plane = Leaf::join(leaves.map(|leaf| Leaf::new(leaf.planes[i])));
+        }
+        for _ in 0..ticks {
+            planes = self.tick(planes);
+        }
+        MultiLeaf::new(planes.map(|plane| Leaf::center_of(plane).cells))
+    }
+}
+
+impl<const N: usize> FromStr for Generations<N> {
+    type Err = RuleError;
+
+    fn from_str(rule: &str) -> Result<Self, Self::Err> {
+        let (rule, neighborhood) = Neighborhood::strip_suffix(rule);
+        let (life, states) = rule.rsplit_once('/').ok_or(RuleError::InvalidFormat)?;
+        let states = states
+            .strip_prefix(['C', 'c', 'G', 'g'])
+            .unwrap_or(states)
+            .parse()
+            .map_err(|_| RuleError::InvalidFormat)?;
+        let life: Totalistic = format!("{}{}", life, neighborhood.suffix()).parse()?;
+        if life.is_birth(0) {
+            return Err(RuleError::UnsupportedB0);
+        }
+        if !Self::is_valid_states(states) {
+            return Err(RuleError::InvalidStates(states));
+        }
+        Ok(Self::new(life, states))
+    }
+}
+
+impl<const N: usize> fmt::Display for Generations<N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.life.write_counts(f)?;
+        let suffix = self.life.neighborhood().suffix();
+        write!(f, "/C{}{}", self.states, suffix)
+    }
+}
+
+#[test]
+fn test_parse() {
+    let brain = Generations::<8>::new(Totalistic::new(&[2], &[]), 3);
+    assert_eq!("B2/S/C3".parse(), Ok(brain));
+    assert_eq!("/2/3".parse(), Ok(brain));
+    assert_eq!("b2/s/g3".parse(), Ok(brain));
+    assert_eq!(brain.to_string(), "B2/S/C3");
+
+    let star_wars: Generations = "345/2/4".parse().unwrap();
+    assert_eq!(star_wars.to_string(), "B2/S345/C4");
+    assert_eq!(star_wars.states(), 4);
+    assert!(star_wars.totalistic().is_survival(5));
+    let hex: Generations = "B2/S34/C5H".parse().unwrap();
+    assert_eq!(hex.totalistic().neighborhood(), Neighborhood::Hexagonal);
+    assert_eq!(hex.to_string(), "B2/S34/C5H");
+
+    assert_eq!(
+        "B3/S23".parse::<Generations>(),
+        Err(RuleError::InvalidFormat)
+    );
+    assert_eq!(
+        "B2/S/C1".parse::<Generations>(),
+        Err(RuleError::InvalidStates(1))
+    );
+    assert_eq!(
+        "B2/S/C5".parse::<Generations<2>>(),
+        Err(RuleError::InvalidStates(5))
+    );
+    assert_eq!(
+        "B02/S/C3".parse::<Generations>(),
+        Err(RuleError::UnsupportedB0)
+    );
+}
+
+#[test]
+fn test_evolve() {
+    use crate::life::Universe;
+    use crate::rule::check_evolve;
+
+    let run = |rule: &str, seed: u64| {
+        let rule: Generations<2> = rule.parse().unwrap();
+        let states = rule.states() as u8;
+        let life = rule.totalistic();
+        let mut universe = Universe::with_rule(rule);
+        check_evolve(
+            &mut universe,
+            160,
+            [0, 1, 2, 3].map(|state| state % states),
+            seed,
+            50,
+            |_, _, get| {
+                let count = (-1..=1)
+                    .flat_map(|dy| (-1..=1).map(move |dx| (dx, dy)))
+                    .filter(|&(dx, dy)| (dx, dy) != (0, 0) && get(dx, dy) == 1)
+                    .count() as u8;
+                match get(0, 0) {
+                    0 => u8::from(life.is_birth(count)),
+                    1 if life.is_survival(count) => 1,
+                    state => (state + 1) % states,
+                }
+            },
+        );
+    };
+
+    run("B2/S/C3", 1);
+    run("345/2/4", 2);
+    run("B3/S23/C2", 3);
+}
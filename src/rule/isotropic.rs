@@ -0,0 +1,454 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::life::LifeRule;
+use crate::rule::table::NeighborhoodTable;
+use crate::rule::{split_rule, RuleError, Totalistic};
+use crate::util::BitGrid;
+
+use std::fmt;
+use std::str::FromStr;
+
+/// The letters of Hensel notation, in the order used by Golly. A cell with `n`
+/// alive neighbors can be in one of `CLASSES[n]` different configurations up
+/// to rotation and reflection, named by the first `CLASSES[n]` letters.
+const LETTERS: &[u8; 13] = b"cekainyqjrtwz";
+
+const CLASSES: [usize; 9] = [1, 2, 6, 10, 13, 10, 6, 2, 1];
+
+/// An example of each configuration of up to four alive neighbors, ordered
+/// like `LETTERS`. Each is a bitmask of the neighbors going clockwise from
+/// the north neighbor in the most significant bit to the northwest neighbor
+/// in the least significant bit. A configuration of five or more neighbors is
+/// the complement of the configuration with the same letter and eight minus
+/// as many neighbors.
+const CONFIGURATIONS: [&[u8]; 5] = [
+    &[0b0000_0000],
+    &[0b0100_0000, 0b1000_0000],
+    &[
+        0b0101_0000,
+        0b1010_0000,
+        0b1001_0000,
+        0b1100_0000,
+        0b1000_1000,
+        0b0100_0100,
+    ],
+    &[
+        0b0101_0100,
+        0b1010_1000,
+        0b1010_0100,
+        0b1110_0000,
+        0b1100_0001,
+        0b1101_0000,
+        0b1001_0100,
+        0b1100_0100,
+        0b1100_0010,
+        0b1100_1000,
+    ],
+    &[
+        0b0101_0101,
+        0b1010_1010,
+        0b1101_0010,
+        0b1111_0000,
+        0b1101_1000,
+        0b1101_0001,
+        0b1101_0100,
+        0b1110_0100,
+        0b1100_1010,
+        0b1110_1000,
+        0b1100_1001,
+        0b1100_0110,
+        0b1100_1100,
+    ],
+];
+
+/// The bits of the `NeighborhoodTable` index for the neighbors going
+/// clockwise from the north.
+const CLOCKWISE: [usize; 8] = [7, 6, 3, 0, 1, 2, 5, 8];
+
+/// The bit of the `NeighborhoodTable` index for the cell itself.
+const CENTER: usize = 4;
+
+/// Returns the letter index of every clockwise neighbor bitmask.
+fn letter_indices() -> [u8; 256] {
+    let mut letters = [0; 256];
+    for count in 0..=8 {
+        for letter in 0..CLASSES[count] {
+            let config = match count {
+                0..=4 => CONFIGURATIONS[count][letter],
+                _ => !CONFIGURATIONS[8 - count][letter],
+            };
+            // The rotations and reflections of the configuration.
+            let reflected = config.reverse_bits().rotate_right(1);
+            for start in [config, reflected] {
+                for quarter_turns in 0..4 {
+                    letters[usize::from(start.rotate_right(2 * quarter_turns))] = letter as u8;
+                }
+            }
+        }
+    }
+    letters
+}
+
+/// An isotropic non-totalistic rule, where the next state of a cell depends on
+/// its state and the configuration of its alive neighbors up to rotation and
+/// reflection.
+///
+/// Parses from Hensel notation such as `B2-a/S12` or `B3/S23-k`, where the
+/// letters after a neighbor count pick configurations of that many neighbors,
+/// or exclude them after a `-`. Totalistic rules fall back to the faster
+/// `Totalistic` rule.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Isotropic {
+    /// Bit `i` of `birth[n]` is set if a dead cell with `n` alive neighbors in
+    /// the configuration named by `LETTERS[i]` is born.
+    birth: [u16; 9],
+    /// Like `birth`, for alive cells that survive.
+    survival: [u16; 9],
+    table: NeighborhoodTable,
+    totalistic: Option<Totalistic>,
+}
+
+impl Isotropic {
+    fn new(birth: [u16; 9], survival: [u16; 9]) -> Self {
+        let letters = letter_indices();
+        let table = NeighborhoodTable::new(|index| {
+            let neighbors = CLOCKWISE
+                .iter()
+                .fold(0u8, |mask, &bit| mask << 1 | (index >> bit & 1) as u8);
+            let count = neighbors.count_ones() as usize;
+            let masks = if index >> CENTER & 1 == 1 {
+                &survival
+            } else {
+                &birth
+            };
+            masks[count] >> letters[usize::from(neighbors)] & 1 == 1
+        });
+
+        let all = |count: usize| (1 << CLASSES[count]) - 1;
+        let counts = |masks: &[u16; 9]| -> Option<Vec<u8>> {
+            let mut counts = vec![];
+            for (count, &mask) in masks.iter().enumerate() {
+                match mask {
+                    0 => {}
+                    _ if mask == all(count) => counts.push(count as u8),
+                    _ => return None,
+                }
+            }
+            Some(counts)
+        };
+        let totalistic = counts(&birth)
+            .zip(counts(&survival))
+            .map(|(birth, survival)| Totalistic::new(&birth, &survival));
+
+        Self {
+            birth,
+            survival,
+            table,
+            totalistic,
+        }
+    }
+
+    /// Returns the equivalent totalistic rule, if there is one.
+    pub fn totalistic(&self) -> Option<Totalistic> {
+        self.totalistic
+    }
+}
+
+impl LifeRule for Isotropic {
+    fn tick<B: BitGrid>(&self, a: B) -> B {
+        match &self.totalistic {
+            Some(totalistic) => totalistic.tick(a),
+            None => self.table.tick(a),
+        }
+    }
+}
+
+/// Reads one part of a rule in Hensel notation, such as `2-a3ijr4`, into a
+/// bitmask of configurations for each neighbor count.
+fn read_configurations(text: &str) -> Result<[u16; 9], RuleError> {
+    let mut masks = [0u16; 9];
+    let mut count = None;
+    let mut negated = false;
+    for c in text.chars() {
+        if let Some(n) = c.to_digit(10).filter(|&n| n <= 8) {
+            let n = n as usize;
+            masks[n] = (1 << CLASSES[n]) - 1;
+            count = Some(n);
+            negated = false;
+            continue;
+        }
+        let n = count.ok_or(RuleError::UnexpectedChar(c))?;
+        if c == '-' && !negated && masks[n] == (1 << CLASSES[n]) - 1 {
+            negated = true;
+            continue;
+        }
+        let letter = LETTERS[..CLASSES[n]]
+            .iter()
+            .position(|&letter| char::from(letter) == c.to_ascii_lowercase())
+            .filter(|_| CLASSES[n] > 1)
+            .ok_or(RuleError::UnexpectedChar(c))?;
+        if negated {
+            masks[n] &= !(1 << letter);
+        } else {
+            // The first letter after a count replaces the whole count.
+            if masks[n] == (1 << CLASSES[n]) - 1 {
+                masks[n] = 0;
+            }
+            masks[n] |= 1 << letter;
+        }
+    }
+    Ok(masks)
+}
+
+/// Writes one part of a rule in Hensel notation, choosing whichever of the
+/// included or excluded letters is shorter for each neighbor count.
+fn write_configurations(f: &mut fmt::Formatter, masks: &[u16; 9]) -> fmt::Result {
+    for (count, &mask) in masks.iter().enumerate() {
+        if mask == 0 {
+            continue;
+        }
+        write!(f, "{}", count)?;
+        let letters = |included: bool| {
+            LETTERS[..CLASSES[count]]
+                .iter()
+                .enumerate()
+                .filter(move |&(i, _)| (mask >> i & 1 == 1) == included)
+                .map(|(_, &letter)| char::from(letter))
+        };
+        let excluded = letters(false).count();
+        if excluded == 0 {
+            continue;
+        }
+        if excluded < letters(true).count() {
+            write!(f, "-")?;
+            letters(false).try_for_each(|letter| write!(f, "{}", letter))?;
+        } else {
+            letters(true).try_for_each(|letter| write!(f, "{}", letter))?;
+        }
+    }
+    Ok(())
+}
+
+impl FromStr for Isotropic {
+    type Err = RuleError;
+
+    fn from_str(rule: &str) -> Result<Self, Self::Err> {
+        let (birth, survival) = split_rule(rule)?;
+        Ok(Self::new(
+            read_configurations(birth)?,
+            read_configurations(survival)?,
+        ))
+    }
+}
+
+impl fmt::Display for Isotropic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "B")?;
+        write_configurations(f, &self.birth)?;
+        write!(f, "/S")?;
+        write_configurations(f, &self.survival)
+    }
+}
+
+#[test]
+fn test_configurations() {
+    // Every arrangement of neighbors has exactly one letter, and every letter
+    // is used, so the configurations are all different.
+    let letters = letter_indices();
+    let mut used = [[0usize; 13]; 9];
+    for neighbors in 0..=255u8 {
+        used[neighbors.count_ones() as usize][usize::from(letters[usize::from(neighbors)])] += 1;
+    }
+    for (count, used) in used.iter().enumerate() {
+        let sizes = &used[..CLASSES[count]];
+        assert!(sizes.iter().all(|&size| size > 0), "{} {:?}", count, sizes);
+        assert!(used[CLASSES[count]..].iter().all(|&size| size == 0));
+    }
+
+    // A few configurations by shape.
+    let letter = |mask: u8| LETTERS[usize::from(letters[usize::from(mask)])] as char;
+    assert_eq!(letter(0b1000_1000), 'i');
+    assert_eq!(letter(0b0010_0010), 'i');
+    assert_eq!(letter(0b0100_0100), 'n');
+    assert_eq!(letter(0b1000_0001), 'a');
+    assert_eq!(letter(0b0101_0101), 'c');
+    assert_eq!(letter(0b0111_0010), 't');
+    assert_eq!(letter(0b0111_0111), 'i');
+}
+
+#[test]
+fn test_parse() {
+    let rule: Isotropic = "B2-a/S12".parse().unwrap();
+    assert_eq!(rule.to_string(), "B2-a/S12");
+    assert_eq!(rule.totalistic(), None);
+
+    let rule: Isotropic = "b3s23-k".parse().unwrap();
+    assert_eq!(rule.to_string(), "B3/S23-k");
+    let rule: Isotropic = "B2ce3aijr/S23".parse().unwrap();
+    assert_eq!(rule.to_string(), "B2ce3aijr/S23");
+    let rule: Isotropic = "B3cekaijr/S23".parse().unwrap();
+    assert_eq!(rule.to_string(), "B3-nyq/S23");
+    let rule: Isotropic = "B2ae3rjqy/S4zwt".parse().unwrap();
+    assert_eq!(rule.to_string(), "B2ea3yqjr/S4twz");
+    let rule: Isotropic = "B2cekain/S4cekainyqjrtwz".parse().unwrap();
+    assert_eq!(rule.to_string(), "B2/S4");
+    assert_eq!(rule.totalistic(), Some(Totalistic::new(&[2], &[4])));
+
+    for bad in ["B2x/S23", "B1a/S23", "B0c/S", "Ba/S", "B2--a/S"] {
+        assert!(bad.parse::<Isotropic>().is_err(), "{}", bad);
+    }
+}
+
+#[test]
+fn test_tick() {
+    use crate::life::soup;
+    use crate::rule::tick_slowly;
+    use std::simd::Simd;
+
+    let letters = letter_indices();
+    for (seed, rule) in ["B2-a/S12", "B3/S23-k", "B2ce3aijr/S2-i34q", "B3/S23"]
+        .into_iter()
+        .enumerate()
+    {
+        let parsed: Isotropic = rule.parse().unwrap();
+        let rows = soup(seed as u64 + 1, !0);
+        let expected = tick_slowly(rows, |alive, window| {
+            // Clockwise from the north, as in `CONFIGURATIONS`.
+            let clockwise = [
+                (0, 1),
+                (0, 2),
+                (1, 2),
+                (2, 2),
+                (2, 1),
+                (2, 0),
+                (1, 0),
+                (0, 0),
+            ];
+            let neighbors = clockwise
+                .iter()
+                .fold(0u8, |mask, &(row, col)| mask << 1 | window[row][col] as u8);
+            let count = neighbors.count_ones();
+            let letter = char::from(LETTERS[usize::from(letters[usize::from(neighbors)])]);
+            let part = if alive {
+                &rule[rule.find('S').unwrap() + 1..]
+            } else {
+                &rule[1..rule.find('/').unwrap()]
+            };
+            // Find the letters for `count` in the rule string.
+            let start = match part.find(char::from_digit(count, 10).unwrap()) {
+                Some(start) => start + 1,
+                None => return false,
+            };
+            let spec: String = part[start..]
+                .chars()
+                .take_while(|c| !c.is_ascii_digit())
+                .collect();
+            match spec.strip_prefix('-') {
+                Some(excluded) => !excluded.contains(letter),
+                None => spec.is_empty() || spec.contains(letter),
+            }
+        });
+        assert_eq!(
+            parsed.tick(Simd::from_array(rows)),
+            Simd::from_array(expected),
+            "{}",
+            rule
+        );
+    }
+
+    // The table agrees with the fast path for totalistic rules.
+    let life: Isotropic = "B3/S23".parse().unwrap();
+    let rows = Simd::from_array(soup(9, !0));
+    assert_eq!(life.table.tick(rows), crate::life::B3S23.tick(rows));
+}
+
+#[test]
+fn test_known_rules() {
+    use std::simd::Simd;
+
+    // Small patterns worked out by hand, each of which only changes as it
+    // does because of the configurations it picks out.
+    let grid = |rows: [&str; 5]| {
+        let mut grid = [0u64; 64];
+        for (row, line) in rows.iter().enumerate() {
+            for (col, c) in line.chars().enumerate() {
+                if c == 'O' {
+                    grid[30 + row] |= 1 << (33 - col);
+                }
+            }
+        }
+        Simd::from_array(grid)
+    };
+    let empty = [".....", ".....", ".....", ".....", "....."];
+    let cases = [
+        // Two cells on a diagonal are both orthogonal neighbors of two cells.
+        (
+            "B2e/S",
+            [".....", ".O...", "..O..", ".....", "....."],
+            [".....", "..O..", ".O...", ".....", "....."],
+        ),
+        (
+            "B2c/S",
+            [".....", ".O...", "..O..", ".....", "....."],
+            empty,
+        ),
+        // Two cells a knight's move apart.
+        (
+            "B2k/S",
+            [".....", ".O...", ".....", "..O..", "....."],
+            [".....", ".....", ".OO..", ".....", "....."],
+        ),
+        (
+            "B2-k/S",
+            [".....", ".O...", ".....", "..O..", "....."],
+            empty,
+        ),
+        // Three cells in a Y around the middle.
+        (
+            "B3y/S",
+            [".....", "..O..", ".....", ".O.O.", "....."],
+            [".....", ".....", "..O..", ".....", "....."],
+        ),
+        (
+            "B3-y/S",
+            [".....", "..O..", ".....", ".O.O.", "....."],
+            empty,
+        ),
+        // In tlife, the middle of a blinker has two neighbors in a line and
+        // dies, and so do the two cells born next to it.
+        (
+            "B3/S2-i34q",
+            [".....", ".....", ".OOO.", ".....", "....."],
+            [".....", "..O..", ".....", "..O..", "....."],
+        ),
+        (
+            "B3/S2-i34q",
+            [".....", "..O..", ".....", "..O..", "....."],
+            empty,
+        ),
+        // The middle cell has four neighbors in a `q`, so it survives in
+        // tlife but not when only four neighbors in an `a` survive.
+        (
+            "B3/S2-i34q",
+            [".....", "..OO.", "..OO.", ".O...", "....."],
+            [".....", "..OO.", ".OOO.", "..O..", "....."],
+        ),
+        (
+            "B3/S2-i34a",
+            [".....", "..OO.", "..OO.", ".O...", "....."],
+            [".....", "..OO.", ".O.O.", "..O..", "....."],
+        ),
+    ];
+    for (rule, before, after) in cases {
+        let rule: Isotropic = rule.parse().unwrap();
+        assert_eq!(
+            rule.tick(grid(before)),
+            grid(after),
+            "{} {:?}",
+            rule,
+            before
+        );
+    }
+}
@@ -0,0 +1,408 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::life::LifeRule;
+use crate::rule::{full_add, Neighborhood, RuleError};
+use crate::util::{BitGrid, Dir};
+
+use std::fmt;
+use std::str::FromStr;
+
+/// The number of bits of a neighbor count, which is enough for the 289 cells
+/// of a Moore neighborhood of radius 8.
+const COUNT_BITS: usize = 9;
+
+/// A Larger than Life rule, an outer-totalistic rule whose neighborhood
+/// reaches up to 8 cells away, with births and survivals given by ranges of
+/// counts.
+///
+/// Parses from Golly's `R5,C0,M1,S34..58,B34..45,NM` notation. `R` gives the
+/// radius, `C0` means two states, `M1` counts the cell itself as one of its
+/// neighbors, `S` and `B` give the ranges of counts for survival and birth,
+/// and `NM`, `NN` or `NH` select the Moore, von Neumann or hexagonal
+/// neighborhood. `C`, `M` and `N` default to `C0`, `M0` and `NM`. Rules with
+/// `B0` aren't supported.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct LargerThanLife {
+    radius: u8,
+    includes_center: bool,
+    survival: (u16, u16),
+    birth: (u16, u16),
+    neighborhood: Neighborhood,
+}
+
+impl LargerThanLife {
+    /// Creates the rule where a dead cell is born if the count of alive cells
+    /// in its neighborhood is in the range `birth`, and an alive cell survives
+    /// if the count is in the range `survival`. The cell itself is counted if
+    /// `includes_center` is `true`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `radius` isn't between 1 and 8, if a range is empty or goes
+    /// beyond the size of the neighborhood, or if the birth range starts at 0.
+    pub fn new(
+        radius: u8,
+        neighborhood: Neighborhood,
+        includes_center: bool,
+        birth: (u16, u16),
+        survival: (u16, u16),
+    ) -> Self {
+        let rule = Self {
+            radius,
+            includes_center,
+            survival,
+            birth,
+            neighborhood,
+        };
+        assert!(
+            (1..=8).contains(&radius),
+            "radius {} is out of range",
+            radius
+        );
+        assert!(birth.0 > 0, "B0 isn't supported");
+        for (min, max) in [birth, survival] {
+            assert!(
+                min <= max && max <= rule.size(),
+                "range {}..{} is out of range",
+                min,
+                max
+            );
+        }
+        rule
+    }
+
+    /// Returns `true` if the cell `dx` cells east and `dy` cells south of a
+    /// cell is in its neighborhood, ignoring the cell itself.
+    fn contains(&self, dx: i8, dy: i8) -> bool {
+        let radius = self.radius as i8;
+        match self.neighborhood {
+            Neighborhood::Moore => true,
+            Neighborhood::VonNeumann => dx.abs() + dy.abs() <= radius,
+            // The neighbors to the northwest and southeast are the closest
+            // diagonal ones, like for `Totalistic`.
+            Neighborhood::Hexagonal => (dx - dy).abs() <= radius,
+        }
+    }
+
+    /// Returns the number of cells that are counted, including the cell
+    /// itself if it is counted.
+    pub fn size(&self) -> u16 {
+        let radius = self.radius as i8;
+        let mut size = u16::from(self.includes_center);
+        for dy in -radius..=radius {
+            for dx in -radius..=radius {
+                size += u16::from((dx, dy) != (0, 0) && self.contains(dx, dy));
+            }
+        }
+        size
+    }
+
+    /// Counts the alive cells in the neighborhood of every cell, returning the
+    /// bits of the count from least to most significant.
+    fn counts<B: BitGrid>(&self, a: B) -> [B; COUNT_BITS] {
+        let radius = self.radius as i8;
+        // The cells `dx` cells to the east, brought into place.
+        let mut columns = vec![a];
+        for dx in 1..=radius {
+            let (west, east) = (
+                columns[0].shift(Dir::East),
+                columns[columns.len() - 1].shift(Dir::West),
+            );
+            columns.insert(0, west);
+            columns.push(east);
+            debug_assert_eq!(columns.len(), 2 * dx as usize + 1);
+        }
+
+        let zero = a & !a;
+        let mut count = [zero; COUNT_BITS];
+        for dy in -radius..=radius {
+            // Sum the cells of one row of the neighborhood.
+            let mut row = [zero; COUNT_BITS];
+            for (dx, &column) in (-radius..=radius).zip(&columns) {
+                let is_center = (dx, dy) == (0, 0);
+                if (is_center && self.includes_center) || (!is_center && self.contains(dx, dy)) {
+                    add(&mut row, &[column]);
+                }
+            }
+            // Bring the row into place and add it to the count.
+            for _ in 0..dy.abs() {
+                row = row.map(|bit| bit.shift(if dy < 0 { Dir::South } else { Dir::North }));
+            }
+            add(&mut count, &row);
+        }
+        count
+    }
+}
+
+/// Adds the bit-sliced number `other` to `count`, dropping any overflow.
+fn add<B: BitGrid>(count: &mut [B], other: &[B]) {
+    let zero = count[0] & !count[0];
+    let mut carry = zero;
+    for (i, bit) in count.iter_mut().enumerate() {
+        let addend = other.get(i).copied().unwrap_or(zero);
+        (*bit, carry) = full_add(*bit, addend, carry);
+    }
+}
+
+/// Returns the cells whose bit-sliced `count` is at least `n`.
+fn at_least<B: BitGrid>(count: &[B], n: u16) -> B {
+    let zero = count[0] & !count[0];
+    if usize::from(n) >= 1 << count.len() {
+        return zero;
+    }
+    // Compare the bits from the most significant one, keeping track of the
+    // cells that are already greater and those that are equal so far.
+    let (mut greater, mut equal) = (zero, !zero);
+    for (i, &bit) in count.iter().enumerate().rev() {
+        if n >> i & 1 == 1 {
+            equal = equal & bit;
+        } else {
+            greater = greater | (equal & bit);
+            equal = equal & !bit;
+        }
+    }
+    greater | equal
+}
+
+impl LifeRule for LargerThanLife {
+    fn tick<B: BitGrid>(&self, a: B) -> B {
+        let count = self.counts(a);
+        let within = |(min, max): (u16, u16)| at_least(&count, min) & !at_least(&count, max + 1);
+        (!a & within(self.birth)) | (a & within(self.survival))
+    }
+
+    fn radius(&self) -> u8 {
+        self.radius
+    }
+}
+
+/// Reads a range of counts such as `34..58`.
+fn read_range(range: &str) -> Result<(u16, u16), RuleError> {
+    let (min, max) = range.split_once("..").ok_or(RuleError::InvalidFormat)?;
+    let parse = |n: &str| n.parse().map_err(|_| RuleError::InvalidFormat);
+    Ok((parse(min)?, parse(max)?))
+}
+
+impl FromStr for LargerThanLife {
+    type Err = RuleError;
+
+    fn from_str(rule: &str) -> Result<Self, Self::Err> {
+        let (mut radius, mut birth, mut survival) = (None, None, None);
+        let (mut includes_center, mut neighborhood) = (false, Neighborhood::Moore);
+        for field in rule.trim().split(',') {
+            let field = field.trim();
+            let value = field.get(1..).ok_or(RuleError::InvalidFormat)?;
+            match field.chars().next().map(|c| c.to_ascii_uppercase()) {
+                Some('R') => {
+                    radius = Some(value.parse().map_err(|_| RuleError::InvalidFormat)?);
+                }
+                Some('C') => match value.parse::<u16>() {
+                    Ok(0..=2) => {}
+                    Ok(states) => return Err(RuleError::InvalidStates(states)),
+                    Err(_) => return Err(RuleError::InvalidFormat),
+                },
+                Some('M') => match value {
+                    "0" => includes_center = false,
+                    "1" => includes_center = true,
+                    _ => return Err(RuleError::InvalidFormat),
+                },
+                Some('S') => survival = Some(read_range(value)?),
+                Some('B') => birth = Some(read_range(value)?),
+                Some('N') => match value.to_ascii_uppercase().as_str() {
+                    "M" => neighborhood = Neighborhood::Moore,
+                    "N" => neighborhood = Neighborhood::VonNeumann,
+                    "H" => neighborhood = Neighborhood::Hexagonal,
+                    _ => return Err(RuleError::InvalidFormat),
+                },
+                _ => return Err(RuleError::InvalidFormat),
+            }
+        }
+
+        let (radius, birth, survival) = match (radius, birth, survival) {
+            (Some(radius @ 1..=8), Some(birth), Some(survival)) => (radius, birth, survival),
+            _ => return Err(RuleError::InvalidFormat),
+        };
+        if birth.0 == 0 {
+            return Err(RuleError::UnsupportedB0);
+        }
+        let rule = Self {
+            radius,
+            includes_center,
+            survival,
+            birth,
+            neighborhood,
+        };
+        let size = rule.size();
+        if [birth, survival]
+            .iter()
+            .any(|&(min, max)| min > max || max > size)
+        {
+            return Err(RuleError::InvalidFormat);
+        }
+        Ok(rule)
+    }
+}
+
+impl fmt::Display for LargerThanLife {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let neighborhood = match self.neighborhood {
+            Neighborhood::Moore => 'M',
+            Neighborhood::VonNeumann => 'N',
+            Neighborhood::Hexagonal => 'H',
+        };
+        write!(
+            f,
+            "R{},C0,M{},S{}..{},B{}..{},N{}",
+            self.radius,
+            u8::from(self.includes_center),
+            self.survival.0,
+            self.survival.1,
+            self.birth.0,
+            self.birth.1,
+            neighborhood
+        )
+    }
+}
+
+#[cfg(test)]
+const BOSCO: &str = "R5,C0,M1,S34..58,B34..45,NM";
+
+#[test]
+fn test_parse() {
+    let bosco: LargerThanLife = BOSCO.parse().unwrap();
+    assert_eq!(
+        bosco,
+        LargerThanLife::new(5, Neighborhood::Moore, true, (34, 45), (34, 58))
+    );
+    assert_eq!(bosco.to_string(), BOSCO);
+    assert_eq!(bosco.size(), 121);
+    assert_eq!(LifeRule::radius(&bosco), 5);
+
+    let rule: LargerThanLife = "R2,S2..4,B3..3,NN".parse().unwrap();
+    assert_eq!(rule.to_string(), "R2,C0,M0,S2..4,B3..3,NN");
+    assert_eq!(rule.size(), 12);
+    let rule: LargerThanLife = "r3,c0,m0,s2..4,b3..5,nh".parse().unwrap();
+    assert_eq!(rule.size(), 36);
+    // Radius 1 is the same as an outer-totalistic rule.
+    let life: LargerThanLife = "R1,C0,M0,S2..3,B3..3,NM".parse().unwrap();
+    assert_eq!(life.size(), 8);
+
+    for rule in [
+        "R5,C0,M1,S34..58,NM",
+        "R9,C0,M1,S34..58,B34..45,NM",
+        "R5,C0,M2,S34..58,B34..45,NM",
+        "R5,C0,M1,S34..58,B34..45,NX",
+        "R5,C0,M1,S34-58,B34..45,NM",
+        "R2,C0,M1,S3..30,B3..4,NM",
+        "R2,C0,M1,S3..3,B4..3,NM",
+        "B3/S23",
+    ] {
+        assert_eq!(
+            rule.parse::<LargerThanLife>(),
+            Err(RuleError::InvalidFormat),
+            "{}",
+            rule
+        );
+    }
+    assert_eq!(
+        "R2,C3,M1,S3..4,B3..4,NM".parse::<LargerThanLife>(),
+        Err(RuleError::InvalidStates(3))
+    );
+    assert_eq!(
+        "R2,C0,M1,S3..4,B0..4,NM".parse::<LargerThanLife>(),
+        Err(RuleError::UnsupportedB0)
+    );
+}
+
+#[test]
+fn test_tick() {
+    use crate::life::{soup, B3S23};
+    use std::simd::Simd;
+
+    let rules = [
+        BOSCO,
+        "R2,C0,M0,S2..4,B3..3,NN",
+        "R3,C0,M0,S6..12,B7..9,NH",
+        "R8,C0,M1,S40..100,B50..70,NM",
+    ];
+    for (seed, rule) in rules.into_iter().enumerate() {
+        let rule: LargerThanLife = rule.parse().unwrap();
+        let rows = soup(seed as u64 + 1, !0);
+        let radius = rule.radius as i8;
+        // The rows wrap around and the columns don't, like `BitGrid`.
+        let get = |row: i64, col: i64| {
+            (0..64).contains(&col) && rows[row.rem_euclid(64) as usize] >> (63 - col) & 1 == 1
+        };
+        let mut expected = [0; 64];
+        for (row, expected) in expected.iter_mut().enumerate() {
+            for col in 0..64 {
+                let (row, col) = (row as i64, col as i64);
+                let mut count = 0;
+                for dy in -radius..=radius {
+                    for dx in -radius..=radius {
+                        let counted = match (dx, dy) {
+                            (0, 0) => rule.includes_center,
+                            _ => rule.contains(dx, dy),
+                        };
+                        count += u16::from(counted && get(row + dy as i64, col + dx as i64));
+                    }
+                }
+                let (min, max) = if get(row, col) {
+                    rule.survival
+                } else {
+                    rule.birth
+                };
+                if (min..=max).contains(&count) {
+                    *expected |= 1 << (63 - col);
+                }
+            }
+        }
+        assert_eq!(
+            rule.tick(Simd::from_array(rows)),
+            Simd::from_array(expected),
+            "{}",
+            rule
+        );
+    }
+
+    let life: LargerThanLife = "R1,C0,M0,S2..3,B3..3,NM".parse().unwrap();
+    let rows = Simd::from_array(soup(7, !0));
+    assert_eq!(life.tick(rows), B3S23.tick(rows));
+}
+
+#[test]
+fn test_evolve() {
+    use crate::life::Universe;
+    use crate::rule::check_evolve;
+
+    let run = |rule: &str, ticks: u64, seed: u64| {
+        let rule: LargerThanLife = rule.parse().unwrap();
+        let radius = rule.radius as i8;
+        let mut universe = Universe::with_rule(rule);
+        check_evolve(&mut universe, 96, [0, 1, 0, 1], seed, ticks, |_, _, get| {
+            let mut count = 0;
+            for dy in -radius..=radius {
+                for dx in -radius..=radius {
+                    let is_center = (dx, dy) == (0, 0);
+                    if (is_center && rule.includes_center) || (!is_center && rule.contains(dx, dy))
+                    {
+                        count += u16::from(get(dx.into(), (-dy).into()));
+                    }
+                }
+            }
+            let (min, max) = if get(0, 0) == 1 {
+                rule.survival
+            } else {
+                rule.birth
+            };
+            u8::from((min..=max).contains(&count))
+        });
+    };
+
+    run(BOSCO, 3, 1);
+    run("R2,C0,M0,S2..4,B3..3,NN", 11, 2);
+    run("R3,C0,M1,S7..13,B8..10,NH", 5, 3);
+    run("R4,C0,M1,S10..30,B20..30,NM", 2, 4);
+}
@@ -0,0 +1,166 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::life::LifeRule;
+use crate::rule::table::NeighborhoodTable;
+use crate::rule::RuleError;
+use crate::util::BitGrid;
+
+use std::fmt;
+use std::str::FromStr;
+
+/// The alphabet of standard base64.
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// The length of the base64 part of a MAP string for the Moore neighborhood,
+/// which encodes 512 bits in 86 characters of 6 bits each.
+const MAP_LEN: usize = 86;
+
+/// A rule given by the next state of a cell for every one of the 512 Moore
+/// neighborhoods, which covers every binary rule on the Moore neighborhood,
+/// including anisotropic ones.
+///
+/// Parses from the `MAP` strings used by Golly and LifeViewer, which hold the
+/// 512 next states in base64. The neighborhoods are numbered by the states of
+/// the cells to the northwest, north, northeast, west, the cell itself, east,
+/// southwest, south and southeast, from the most to the least significant
+/// bit, and their next states are stored from neighborhood 0 onwards, most
+/// significant bit first.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct MapRule {
+    table: NeighborhoodTable,
+}
+
+impl MapRule {
+    /// Creates the rule where a cell with the neighborhood `index` is alive in
+    /// the next generation if `alive(index)` is `true`.
+    pub fn from_fn(mut alive: impl FnMut(u16) -> bool) -> Self {
+        Self {
+            table: NeighborhoodTable::new(|index| alive(index as u16)),
+        }
+    }
+
+    /// Returns `true` if a cell with the neighborhood `index` is alive in the
+    /// next generation.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is 512 or more.
+    pub fn get(&self, index: u16) -> bool {
+        assert!(index < 512, "neighborhood {} is out of range", index);
+        self.table.get(usize::from(index))
+    }
+}
+
+impl LifeRule for MapRule {
+    fn tick<B: BitGrid>(&self, a: B) -> B {
+        self.table.tick(a)
+    }
+}
+
+impl FromStr for MapRule {
+    type Err = RuleError;
+
+    fn from_str(rule: &str) -> Result<Self, Self::Err> {
+        let data = rule
+            .trim()
+            .strip_prefix("MAP")
+            .ok_or(RuleError::InvalidFormat)?;
+        // Golly writes MAP strings without padding, but other programs may add
+        // it.
+        let data = data.strip_suffix("==").unwrap_or(data);
+        if data.len() != MAP_LEN {
+            return Err(RuleError::InvalidLength(data.len()));
+        }
+
+        let mut bits = [false; 512];
+        for (i, c) in data.chars().enumerate() {
+            let value = BASE64
+                .iter()
+                .position(|&digit| char::from(digit) == c)
+                .ok_or(RuleError::UnexpectedChar(c))?;
+            for bit in 0..6 {
+                if let Some(alive) = bits.get_mut(6 * i + bit) {
+                    *alive = value >> (5 - bit) & 1 == 1;
+                }
+            }
+        }
+        Ok(Self::from_fn(|index| bits[usize::from(index)]))
+    }
+}
+
+impl fmt::Display for MapRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "MAP")?;
+        for i in 0..MAP_LEN {
+            let value = (0..6).fold(0, |value, bit| {
+                let index = 6 * i + bit;
+                value << 1 | usize::from(index < 512 && self.table.get(index))
+            });
+            write!(f, "{}", char::from(BASE64[value]))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+const LIFE: &str =
+    "MAPARYXfhZofugWaH7oaIDogBZofuhogOiAaIDogIAAgAAWaH7oaIDogGiA6ICAAIAAaIDogIAAgACAAIAAAAAAAA";
+
+#[test]
+fn test_parse() {
+    let life = MapRule::from_fn(|index| {
+        let count = (index & !0b1_0000).count_ones();
+        count == 3 || (count == 2 && index & 0b1_0000 != 0)
+    });
+    assert_eq!(life.to_string(), LIFE);
+    assert_eq!(LIFE.parse(), Ok(life.clone()));
+    assert_eq!(format!("{}==", LIFE).parse(), Ok(life));
+
+    assert_eq!(
+        "MAPARYX".parse::<MapRule>(),
+        Err(RuleError::InvalidLength(4))
+    );
+    assert_eq!(
+        LIFE.replace('h', "!").parse::<MapRule>(),
+        Err(RuleError::UnexpectedChar('!'))
+    );
+    assert_eq!("B3/S23".parse::<MapRule>(), Err(RuleError::InvalidFormat));
+}
+
+#[test]
+fn test_evolve() {
+    use crate::life::{NodeArena, Universe, B3S23};
+    use crate::pattern::read_rle;
+    use crate::util::Vec2;
+
+    // Life runs the same through HashLife as a MAP rule.
+    let gun = include_str!("../../assets/gosperglidergun.rle");
+    let mut life = Universe::<B3S23>::new();
+    let (root, _) = read_rle(gun.as_bytes(), life.nodes_mut()).unwrap();
+    let expected = life.evolve(root, 300).unwrap();
+    let mut map = Universe::with_rule(LIFE.parse::<MapRule>().unwrap());
+    let (root, _) = read_rle(gun.as_bytes(), map.nodes_mut()).unwrap();
+    let result = map.evolve(root, 300).unwrap();
+
+    let cells = |nodes: &NodeArena<_>, root| {
+        let mut cells = vec![];
+        nodes.for_each_alive(root, |cell| cells.push(cell)).unwrap();
+        cells.sort_by_key(|cell: &Vec2| (cell.x, cell.y));
+        cells
+    };
+    assert_eq!(cells(map.nodes(), result), cells(life.nodes(), expected));
+
+    // An anisotropic rule where every cell copies its southwest neighbor, so
+    // the whole pattern moves one cell northeast each generation.
+    let mut shift = Universe::with_rule(MapRule::from_fn(|index| index & 0b100 != 0));
+    let (root, _) = read_rle(gun.as_bytes(), shift.nodes_mut()).unwrap();
+    let before = cells(shift.nodes(), root);
+    let result = shift.evolve(root, 10).unwrap();
+    let moved: Vec<_> = before
+        .into_iter()
+        .map(|cell| cell + Vec2::new(10, 10))
+        .collect();
+    assert_eq!(cells(shift.nodes(), result), moved);
+}
@@ -0,0 +1,261 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::leaf::Leaf;
+use crate::life::{Phase, Rule};
+use crate::rule::RuleError;
+use crate::util::{BitGrid, Dir};
+
+use std::fmt;
+use std::simd::u16x16;
+use std::str::FromStr;
+
+/// The rows of a leaf that are at the top of a block on even generations.
+const EVEN_ROWS: u16x16 =
+    u16x16::from_array([!0, 0, !0, 0, !0, 0, !0, 0, !0, 0, !0, 0, !0, 0, !0, 0]);
+
+/// A block rule on the Margolus neighborhood, where the grid is split into
+/// blocks of 2 by 2 cells and each block is replaced as a whole.
+///
+/// The blocks alternate between two partitions. On even generations the
+/// northwest cell of every block has an even `x` and an odd `y`, and on odd
+/// generations the blocks are moved one cell to the east and south. A block
+/// is numbered by its cells, with the northwest cell as 1, the northeast cell
+/// as 2, the southwest cell as 4 and the southeast cell as 8.
+///
+/// Parses from the `MS,D` notation of Golly and MCell, which lists the new
+/// number of every block from block 0 to block 15, separated by `;`. The
+/// empty block must become either empty or full, and if it becomes full then
+/// so must the full block.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Margolus {
+    blocks: [u8; 16],
+}
+
+impl Margolus {
+    /// Creates the rule where the block numbered `i` becomes `blocks[i]`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a block number is 16 or more, or if the empty block doesn't
+    /// stay uniform.
+    pub fn new(blocks: [u8; 16]) -> Self {
+        assert!(
+            blocks.iter().all(|&block| block < 16),
+            "block numbers are out of range"
+        );
+        assert!(
+            Self::is_uniform(blocks),
+            "the empty block must become empty or full"
+        );
+        Self { blocks }
+    }
+
+    /// Returns `true` if the empty block becomes empty or full, and the full
+    /// block does too if it can be reached.
+    fn is_uniform(blocks: [u8; 16]) -> bool {
+        match blocks[0] {
+            0 => true,
+            15 => blocks[15] == 0 || blocks[15] == 15,
+            _ => false,
+        }
+    }
+
+    /// Critters, where blocks with two alive cells stay the same, and every
+    /// other block is inverted and, if it had three alive cells, turned by
+    /// half a turn.
+    pub fn critters() -> Self {
+        Self::new([15, 14, 13, 3, 11, 5, 6, 1, 7, 9, 10, 2, 12, 4, 8, 0])
+    }
+
+    /// Tron, where empty and full blocks are inverted and every other block
+    /// stays the same.
+    pub fn tron() -> Self {
+        Self::new([15, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 0])
+    }
+
+    /// The billiard ball model, where a single alive cell moves diagonally
+    /// across its block and two cells that meet head on bounce off at right
+    /// angles.
+    pub fn billiard_ball() -> Self {
+        Self::new([0, 8, 4, 3, 2, 5, 9, 7, 1, 6, 10, 11, 12, 13, 14, 15])
+    }
+
+    /// Returns the number of the block that the block numbered `block`
+    /// becomes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `block` is 16 or more.
+    pub fn get(&self, block: u8) -> u8 {
+        self.blocks[usize::from(block)]
+    }
+
+    /// Advances the cells of a leaf by one generation, on even or odd
+    /// generations depending on `odd`. Blocks that cross the edges of the leaf
+    /// see dead cells beyond them.
+    fn tick(&self, cells: u16x16, odd: bool) -> u16x16 {
+        let (west, north) = if odd {
+            (u16x16::splat(0x5555), !EVEN_ROWS)
+        } else {
+            (u16x16::splat(0xaaaa), EVEN_ROWS)
+        };
+        let (east, south) = (!west, !north);
+
+        // Gather the four cells of its block into the place of every cell,
+        // first along the rows and then along the columns.
+        let (from_west, from_east) = (cells.shift(Dir::East), cells.shift(Dir::West));
+        let row_west = (west & cells) | (east & from_west);
+        let row_east = (west & from_east) | (east & cells);
+        let top = |row: u16x16| (north & row) | (south & row.shift(Dir::South));
+        let bottom = |row: u16x16| (north & row.shift(Dir::North)) | (south & row);
+        let corners = [
+            top(row_west),
+            top(row_east),
+            bottom(row_west),
+            bottom(row_east),
+        ];
+
+        // Every cell takes the state of its own corner of the new block.
+        let places = [west & north, east & north, west & south, east & south];
+        let zero = u16x16::splat(0);
+        let mut next = zero;
+        for (block, &new) in (0..).zip(&self.blocks) {
+            let is_block =
+                corners
+                    .zip([0, 1, 2, 3])
+                    .iter()
+                    .fold(!zero, |is_block, &(corner, i)| {
+                        is_block & if block >> i & 1 == 1 { corner } else { !corner }
+                    });
+            for (i, &place) in places.iter().enumerate() {
+                if new >> i & 1 == 1 {
+                    next |= is_block & place;
+                }
+            }
+        }
+        next
+    }
+}
+
+impl Rule for Margolus {
+    type Leaf = Leaf;
+
+    fn next_background(&self, background: bool) -> bool {
+        self.blocks[if background { 15 } else { 0 }] == 15
+    }
+
+    fn alternates(&self) -> bool {
+        true
+    }
+
+    fn tick_leaf(&self, leaf: Leaf, phase: Phase, next: Phase) -> Leaf {
+        let fill = |alive: bool| u16x16::splat(if alive { !0 } else { 0 });
+        let cells = self.tick(leaf.cells ^ fill(phase.background), phase.odd);
+        Leaf::new(cells ^ fill(next.background))
+    }
+}
+
+impl FromStr for Margolus {
+    type Err = RuleError;
+
+    fn from_str(rule: &str) -> Result<Self, Self::Err> {
+        let rule = rule.trim();
+        let data = rule
+            .get(..4)
+            .filter(|prefix| prefix.eq_ignore_ascii_case("MS,D"))
+            .map(|_| &rule[4..])
+            .ok_or(RuleError::InvalidFormat)?;
+        let blocks: Vec<u8> = data
+            .split(';')
+            .map(|block| match block.trim().parse() {
+                Ok(block) if block < 16 => Ok(block),
+                _ => Err(RuleError::InvalidFormat),
+            })
+            .collect::<Result<_, _>>()?;
+        let blocks: [u8; 16] = blocks
+            .try_into()
+            .map_err(|blocks: Vec<u8>| RuleError::InvalidLength(blocks.len()))?;
+        if !Self::is_uniform(blocks) {
+            return Err(RuleError::UnsupportedB0);
+        }
+        Ok(Self::new(blocks))
+    }
+}
+
+impl fmt::Display for Margolus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "MS,D{}", self.blocks[0])?;
+        for block in &self.blocks[1..] {
+            write!(f, ";{}", block)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_parse() {
+    let critters = "MS,D15;14;13;3;11;5;6;1;7;9;10;2;12;4;8;0";
+    assert_eq!(critters.parse(), Ok(Margolus::critters()));
+    assert_eq!(Margolus::critters().to_string(), critters);
+    assert_eq!(
+        "ms,d0;8;4;3;2;5;9;7;1;6;10;11;12;13;14;15".parse(),
+        Ok(Margolus::billiard_ball())
+    );
+    assert_eq!(Margolus::tron().get(15), 0);
+
+    assert_eq!(
+        "MS,D0;1;2".parse::<Margolus>(),
+        Err(RuleError::InvalidLength(3))
+    );
+    assert_eq!(
+        "MS,D0;1;2;3;4;5;6;7;8;9;10;11;12;13;14;16".parse::<Margolus>(),
+        Err(RuleError::InvalidFormat)
+    );
+    assert_eq!(
+        "MS,D1;1;2;3;4;5;6;7;8;9;10;11;12;13;14;15".parse::<Margolus>(),
+        Err(RuleError::UnsupportedB0)
+    );
+    assert_eq!("B3/S23".parse::<Margolus>(), Err(RuleError::InvalidFormat));
+}
+
+#[test]
+fn test_evolve() {
+    use crate::life::Universe;
+    use crate::rule::check_evolve;
+
+    let run = |rule: Margolus, seed: u64| {
+        let mut universe = Universe::with_rule(rule);
+        check_evolve(
+            &mut universe,
+            128,
+            [0, 1, 0, 1],
+            seed,
+            40,
+            |generation, cell, get| {
+                // The offsets of the northwest cell of the block from the cell.
+                let odd = (generation % 2) as i64;
+                let (dx, dy) = (
+                    -(cell.x - odd).rem_euclid(2),
+                    (cell.y + 1 - odd).rem_euclid(2),
+                );
+                let corners = [(0, 0), (1, 0), (0, -1), (1, -1)];
+                let block = corners
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &(x, y))| get(dx + x, dy + y) << i)
+                    .sum();
+                let i = corners
+                    .iter()
+                    .position(|&corner| corner == (-dx, -dy))
+                    .unwrap();
+                rule.get(block) >> i & 1
+            },
+        );
+    };
+
+    run(Margolus::billiard_ball(), 1);
+    run(Margolus::critters(), 2);
+    run(Margolus::tron(), 3);
+}
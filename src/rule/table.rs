@@ -0,0 +1,139 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::util::{BitGrid, Dir};
+
+use fnv::FnvHashMap;
+
+/// The number of cells in a Moore neighborhood, including the cell itself.
+const CELLS: usize = 9;
+
+/// A decision node of a `NeighborhoodTable`, which picks `hi` if the cell at
+/// position `var` of the neighborhood is alive and `lo` otherwise. The values
+/// 0 and 1 stand for dead and alive, and a value `i` of 2 or more is the
+/// output of gate `i - 2`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct Gate {
+    var: u8,
+    lo: u16,
+    hi: u16,
+}
+
+/// The next state of a cell for each of the 512 possible neighborhoods.
+///
+/// A neighborhood is indexed by the states of the cells to the northwest,
+/// north, northeast, west, the cell itself, east, southwest, south and
+/// southeast, from the most to the least significant bit. This is the same
+/// order as Golly's MAP strings.
+///
+/// The table is compiled into a reduced binary decision diagram, which is
+/// evaluated as a circuit over whole bit grids at once.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct NeighborhoodTable {
+    bits: [u64; 8],
+    gates: Vec<Gate>,
+    /// The value that gives the next state.
+    root: u16,
+}
+
+impl NeighborhoodTable {
+    pub(crate) fn new(mut alive: impl FnMut(usize) -> bool) -> Self {
+        let mut bits = [0u64; 8];
+        for index in 0..1 << CELLS {
+            if alive(index) {
+                bits[index / 64] |= 1 << (index % 64);
+            }
+        }
+
+        let mut table = Self {
+            bits,
+            gates: vec![],
+            root: 0,
+        };
+        table.root = table.compile(0, 0, &mut FnvHashMap::default());
+        table
+    }
+
+    /// Returns the next state of a cell with the neighborhood `index`.
+    pub(crate) fn get(&self, index: usize) -> bool {
+        self.bits[index / 64] >> (index % 64) & 1 == 1
+    }
+
+    /// Builds the gates for the neighborhoods whose first `var` cells are
+    /// given by `prefix`, returning the value that selects their next state.
+    fn compile(&mut self, var: u8, prefix: usize, unique: &mut FnvHashMap<Gate, u16>) -> u16 {
+        if usize::from(var) == CELLS {
+            return u16::from(self.get(prefix));
+        }
+        let lo = self.compile(var + 1, prefix << 1, unique);
+        let hi = self.compile(var + 1, prefix << 1 | 1, unique);
+        if lo == hi {
+            return lo;
+        }
+        let gate = Gate { var, lo, hi };
+        *unique.entry(gate).or_insert_with(|| {
+            self.gates.push(gate);
+            self.gates.len() as u16 + 1
+        })
+    }
+
+    pub(crate) fn tick<B: BitGrid>(&self, a: B) -> B {
+        let cells = neighborhood(a);
+        let dead = a & !a;
+        let mut values = Vec::with_capacity(self.gates.len() + 2);
+        values.extend([dead, !dead]);
+        for gate in &self.gates {
+            let cell = cells[usize::from(gate.var)];
+            let (lo, hi) = (values[usize::from(gate.lo)], values[usize::from(gate.hi)]);
+            values.push((cell & hi) | (!cell & lo));
+        }
+        values[usize::from(self.root)]
+    }
+}
+
+/// Returns the cells of the Moore neighborhood of every cell, in the order of
+/// `NeighborhoodTable`.
+pub(crate) fn neighborhood<B: BitGrid>(a: B) -> [B; CELLS] {
+    // Shifting the grid south brings the neighbor to the north of every cell
+    // into its place, and so on.
+    let row = |b: B| [b.shift(Dir::East), b, b.shift(Dir::West)];
+    let [nw, n, ne] = row(a.shift(Dir::South));
+    let [w, c, e] = row(a);
+    let [sw, s, se] = row(a.shift(Dir::North));
+    [nw, n, ne, w, c, e, sw, s, se]
+}
+
+#[test]
+fn test_table() {
+    use crate::life::soup;
+    use crate::rule::tick_slowly;
+    use std::simd::Simd;
+
+    let index = |window: [[bool; 3]; 3]| {
+        window
+            .iter()
+            .flatten()
+            .fold(0, |index, &cell| index << 1 | usize::from(cell))
+    };
+    // An anisotropic rule, where a cell copies its northeast neighbor unless
+    // its south neighbor is alive.
+    let shift = NeighborhoodTable::new(|index| index & 0b0_0100_0000 != 0 && index & 0b10 == 0);
+    // A rule with no pattern to it at all.
+    let random = soup(99, !0);
+    let random = NeighborhoodTable::new(|index| random[index % 64] >> (index / 64) & 1 == 1);
+
+    for (seed, table) in [shift, random].into_iter().enumerate() {
+        let rows = soup(seed as u64 + 1, !0);
+        let expected = tick_slowly(rows, |_, window| table.get(index(window)));
+        assert_eq!(
+            table.tick(Simd::from_array(rows)),
+            Simd::from_array(expected)
+        );
+    }
+
+    // Constant rules need no gates.
+    let rows = Simd::from_array(soup(5, !0));
+    assert_eq!(NeighborhoodTable::new(|_| false).tick(rows), Simd::splat(0));
+    assert_eq!(NeighborhoodTable::new(|_| true).tick(rows), Simd::splat(!0));
+}
@@ -0,0 +1,306 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::life::LifeRule;
+use crate::rule::{count_equals, moore_counts, neighbor_counts, split_rule, RuleError};
+use crate::util::{BitGrid, Dir};
+
+use std::fmt;
+use std::str::FromStr;
+
+/// The cells around a cell that an outer-totalistic rule counts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Neighborhood {
+    /// The eight orthogonal and diagonal neighbors.
+    Moore,
+    /// The four orthogonal neighbors, given by a `V` suffix on the rule.
+    VonNeumann,
+    /// The six neighbors of a hexagonal grid drawn on the square grid with
+    /// each row skewed, which are the Moore neighbors except the northeast and
+    /// southwest ones. Given by an `H` suffix on the rule.
+    Hexagonal,
+}
+
+impl Neighborhood {
+    /// Returns the number of cells in the neighborhood.
+    pub fn size(self) -> u8 {
+        match self {
+            Self::Moore => 8,
+            Self::VonNeumann => 4,
+            Self::Hexagonal => 6,
+        }
+    }
+
+    /// Returns the suffix that selects the neighborhood in a rule string.
+    pub(crate) fn suffix(self) -> &'static str {
+        match self {
+            Self::Moore => "",
+            Self::VonNeumann => "V",
+            Self::Hexagonal => "H",
+        }
+    }
+
+    /// Splits the neighborhood suffix off the end of a rule string.
+    pub(crate) fn strip_suffix(rule: &str) -> (&str, Self) {
+        let rule = rule.trim();
+        if let Some(rule) = rule.strip_suffix(['V', 'v']) {
+            (rule, Self::VonNeumann)
+        } else if let Some(rule) = rule.strip_suffix(['H', 'h']) {
+            (rule, Self::Hexagonal)
+        } else {
+            (rule, Self::Moore)
+        }
+    }
+
+    /// Counts the alive neighbors of every cell, returning the four bits of
+    /// the count from least to most significant.
+    pub(crate) fn counts<B: BitGrid>(self, a: B) -> [B; 4] {
+        if self == Self::Moore {
+            return moore_counts(a);
+        }
+        // Shifting the grid south brings the north neighbor of each cell into
+        // its place, and so on.
+        let (north, south) = (a.shift(Dir::South), a.shift(Dir::North));
+        let (west, east) = (a.shift(Dir::East), a.shift(Dir::West));
+        if self == Self::VonNeumann {
+            return neighbor_counts(&[north, south, west, east]);
+        }
+        let (northwest, southeast) = (north.shift(Dir::East), south.shift(Dir::West));
+        neighbor_counts(&[north, south, west, east, northwest, southeast])
+    }
+}
+
+impl Default for Neighborhood {
+    fn default() -> Self {
+        Self::Moore
+    }
+}
+
+/// An outer-totalistic rule, where the next state of a cell depends only on
+/// its state and the number of its alive neighbors.
+///
+/// Parses from the `B36/S23` notation, with or without the slash and in
+/// either case, and from the older `23/36` notation that gives the survival
+/// counts first. The neighbors are the Moore neighborhood, unless the rule
+/// ends with `V` for the von Neumann neighborhood or `H` for the hexagonal
+/// one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Totalistic {
+    /// Bit `n` is set if a dead cell with `n` alive neighbors is born.
+    birth: u16,
+    /// Bit `n` is set if an alive cell with `n` alive neighbors survives.
+    survival: u16,
+    neighborhood: Neighborhood,
+}
+
+impl Totalistic {
+    /// Creates the rule where a dead cell with `n` alive Moore neighbors is
+    /// born if `birth` contains `n`, and an alive cell survives if `survival`
+    /// contains `n`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a neighbor count is greater than 8.
+    pub fn new(birth: &[u8], survival: &[u8]) -> Self {
+        Self::with_neighborhood(birth, survival, Neighborhood::Moore)
+    }
+
+    /// Creates the rule like `new`, but counting the alive cells in
+    /// `neighborhood`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a neighbor count is greater than the size of the
+    /// neighborhood.
+    pub fn with_neighborhood(birth: &[u8], survival: &[u8], neighborhood: Neighborhood) -> Self {
+        let mask = |counts: &[u8]| {
+            counts.iter().fold(0, |mask, &n| {
+                assert!(
+                    n <= neighborhood.size(),
+                    "neighbor count {} is out of range",
+                    n
+                );
+                mask | 1 << n
+            })
+        };
+        Self {
+            birth: mask(birth),
+            survival: mask(survival),
+            neighborhood,
+        }
+    }
+
+    pub fn neighborhood(&self) -> Neighborhood {
+        self.neighborhood
+    }
+
+    /// Returns `true` if a dead cell with `n` alive neighbors is born.
+    pub fn is_birth(&self, n: u8) -> bool {
+        self.birth >> n & 1 == 1
+    }
+
+    /// Returns `true` if an alive cell with `n` alive neighbors survives.
+    pub fn is_survival(&self, n: u8) -> bool {
+        self.survival >> n & 1 == 1
+    }
+}
+
+impl Default for Totalistic {
+    fn default() -> Self {
+        Self::new(&[3], &[2, 3])
+    }
+}
+
+impl LifeRule for Totalistic {
+    fn tick<B: BitGrid>(&self, a: B) -> B {
+        let count = self.neighborhood.counts(a);
+        let zero = a & !a;
+        let (mut born, mut survives) = (zero, zero);
+        for n in 0..=self.neighborhood.size() {
+            if self.is_birth(n) || self.is_survival(n) {
+                let equals = count_equals(count, n);
+                if self.is_birth(n) {
+                    born = born | equals;
+                }
+                if self.is_survival(n) {
+                    survives = survives | equals;
+                }
+            }
+        }
+        (!a & born) | (a & survives)
+    }
+}
+
+/// Reads a list of neighbor counts such as `236` into a bitmask, where no
+/// count may be more than `max`.
+fn read_counts(digits: &str, max: u8) -> Result<u16, RuleError> {
+    digits.chars().try_fold(0, |mask, c| match c.to_digit(10) {
+        Some(n) if n <= u32::from(max) => Ok(mask | 1 << n),
+        _ => Err(RuleError::UnexpectedChar(c)),
+    })
+}
+
+impl FromStr for Totalistic {
+    type Err = RuleError;
+
+    fn from_str(rule: &str) -> Result<Self, Self::Err> {
+        let (rule, neighborhood) = Neighborhood::strip_suffix(rule);
+        let (birth, survival) = split_rule(rule)?;
+        Ok(Self {
+            birth: read_counts(birth, neighborhood.size())?,
+            survival: read_counts(survival, neighborhood.size())?,
+            neighborhood,
+        })
+    }
+}
+
+impl Totalistic {
+    /// Writes the birth and survival counts, without the neighborhood suffix.
+    pub(crate) fn write_counts(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "B")?;
+        for n in (0..=8).filter(|&n| self.is_birth(n)) {
+            write!(f, "{}", n)?;
+        }
+        write!(f, "/S")?;
+        for n in (0..=8).filter(|&n| self.is_survival(n)) {
+            write!(f, "{}", n)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Totalistic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write_counts(f)?;
+        write!(f, "{}", self.neighborhood.suffix())
+    }
+}
+
+#[test]
+fn test_parse() {
+    let life = Totalistic::default();
+    for rule in ["B3/S23", "b3s23", "B3S23", "S23/B3", "23/3", " B3/S23 "] {
+        assert_eq!(rule.parse(), Ok(life), "{}", rule);
+    }
+    assert_eq!("23/36".parse(), Ok(Totalistic::new(&[3, 6], &[2, 3])));
+    assert_eq!("B2/S".parse(), Ok(Totalistic::new(&[2], &[])));
+    assert_eq!("/2".parse(), Ok(Totalistic::new(&[2], &[])));
+    assert_eq!(
+        "B3678/S34678".parse::<Totalistic>().unwrap().to_string(),
+        "B3678/S34678"
+    );
+
+    assert_eq!(
+        "B39/S23".parse::<Totalistic>(),
+        Err(RuleError::UnexpectedChar('9'))
+    );
+    assert_eq!(
+        "B3/X23".parse::<Totalistic>(),
+        Err(RuleError::InvalidFormat)
+    );
+    assert_eq!("B3".parse::<Totalistic>(), Err(RuleError::InvalidFormat));
+
+    let von_neumann = Totalistic::with_neighborhood(&[1], &[1, 3], Neighborhood::VonNeumann);
+    assert_eq!("B1/S13V".parse(), Ok(von_neumann));
+    assert_eq!("13/1v".parse(), Ok(von_neumann));
+    assert_eq!(von_neumann.to_string(), "B1/S13V");
+    assert_eq!(
+        "B2/S34H".parse::<Totalistic>().unwrap().neighborhood(),
+        Neighborhood::Hexagonal
+    );
+    assert_eq!(
+        "B2/S35V".parse::<Totalistic>(),
+        Err(RuleError::UnexpectedChar('5'))
+    );
+    assert_eq!(
+        "B27/S34H".parse::<Totalistic>(),
+        Err(RuleError::UnexpectedChar('7'))
+    );
+    assert_eq!("23".parse::<Totalistic>(), Err(RuleError::InvalidFormat));
+}
+
+#[test]
+fn test_tick() {
+    use crate::life::{soup, B3S23};
+    use crate::rule::tick_slowly;
+    use std::simd::Simd;
+
+    let rules = [
+        "B3/S23",
+        "B36/S23",
+        "B3678/S34678",
+        "B2/S",
+        "B1357/S02468",
+        "B13/S024V",
+        "B2/S34H",
+        "B135/S0246H",
+    ];
+    for (seed, rule) in rules.into_iter().enumerate() {
+        let rule: Totalistic = rule.parse().unwrap();
+        let rows = soup(seed as u64 + 1, !0);
+        let expected = tick_slowly(rows, |_, window| {
+            let [[nw, n, ne], [w, alive, e], [sw, s, se]] = window;
+            let neighbors = match rule.neighborhood() {
+                Neighborhood::Moore => vec![nw, n, ne, w, e, sw, s, se],
+                Neighborhood::VonNeumann => vec![n, w, e, s],
+                Neighborhood::Hexagonal => vec![nw, n, w, e, s, se],
+            };
+            let count = neighbors.into_iter().filter(|&cell| cell).count() as u8;
+            if alive {
+                rule.is_survival(count)
+            } else {
+                rule.is_birth(count)
+            }
+        });
+        assert_eq!(
+            rule.tick(Simd::from_array(rows)),
+            Simd::from_array(expected),
+            "{}",
+            rule
+        );
+    }
+
+    let rows = Simd::from_array(soup(7, !0));
+    assert_eq!(Totalistic::default().tick(rows), B3S23.tick(rows));
+}
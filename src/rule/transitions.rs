@@ -0,0 +1,579 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::rule::tree::TreeNode;
+use crate::rule::RuleError;
+
+use fnv::{FnvHashMap, FnvHashSet};
+
+/// A set of cell states, as a bitmask over all 256 possible states.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+struct States([u64; 4]);
+
+impl States {
+    const EMPTY: Self = Self([0; 4]);
+
+    fn single(state: u8) -> Self {
+        let mut states = Self::EMPTY;
+        states.0[usize::from(state / 64)] |= 1 << (state % 64);
+        states
+    }
+
+    fn union(self, other: Self) -> Self {
+        Self(self.0.zip(other.0).map(|(a, b)| a | b))
+    }
+
+    fn contains(&self, state: u8) -> bool {
+        self.0[usize::from(state / 64)] >> (state % 64) & 1 == 1
+    }
+
+    fn iter(self) -> impl Iterator<Item = u8> {
+        (0..=u8::MAX).filter(move |&state| self.contains(state))
+    }
+}
+
+/// The neighborhoods that a rule table can be written for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum TableNeighborhood {
+    Moore,
+    VonNeumann,
+    Hexagonal,
+}
+
+impl TableNeighborhood {
+    /// Returns the number of neighbors in a transition. They come after the
+    /// cell itself, clockwise from the north.
+    fn ring(self) -> usize {
+        match self {
+            Self::Moore => 8,
+            Self::VonNeumann => 4,
+            Self::Hexagonal => 6,
+        }
+    }
+
+    /// Returns the number of neighbors of the rule tree for the table.
+    /// Hexagonal tables become trees on the Moore neighborhood that ignore
+    /// the northeast and southwest neighbors.
+    fn tree_neighbors(self) -> u8 {
+        match self {
+            Self::Moore | Self::Hexagonal => 8,
+            Self::VonNeumann => 4,
+        }
+    }
+
+    /// Returns the entry of a transition for each cell that the rule tree
+    /// looks at, in the order of the tree, or `None` for a cell that the
+    /// table ignores.
+    fn tree_entries(self) -> &'static [Option<usize>] {
+        match self {
+            // The entries are the cell, then N, NE, E, SE, S, SW, W and NW,
+            // and the tree looks at NW, NE, SW, SE, N, W, E, S and the cell.
+            Self::Moore => &[
+                Some(8),
+                Some(2),
+                Some(6),
+                Some(4),
+                Some(1),
+                Some(7),
+                Some(3),
+                Some(5),
+                Some(0),
+            ],
+            // The entries are the cell, then N, E, S and W, and the tree
+            // looks at N, W, E, S and the cell.
+            Self::VonNeumann => &[Some(1), Some(4), Some(2), Some(3), Some(0)],
+            // The entries are the cell, then N, E, SE, S, W and NW.
+            Self::Hexagonal => &[
+                Some(6),
+                None,
+                None,
+                Some(3),
+                Some(1),
+                Some(5),
+                Some(2),
+                Some(4),
+                Some(0),
+            ],
+        }
+    }
+}
+
+/// The ways that the neighbors of a transition may be rearranged.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Symmetry {
+    /// The rearrangements as permutations, where neighbor `i` of a new
+    /// transition is neighbor `p[i]` of the original one.
+    Group(Vec<Vec<usize>>),
+    /// Every rearrangement.
+    Permute,
+}
+
+impl Symmetry {
+    /// Reads the symmetries of a table with `ring` neighbors, such as
+    /// `rotate4reflect`. The mirror image from west to east is
+    /// `reflect_horizontal`, or `reflect` for short.
+    fn new(name: &str, ring: usize) -> Option<Self> {
+        let identity: Vec<usize> = (0..ring).collect();
+        let mirror = |p: &Vec<usize>| p.iter().map(|&i| (ring - i) % ring).collect();
+        let (rotations, reflect) = match name {
+            "none" => return Some(Self::Group(vec![identity])),
+            "permute" => return Some(Self::Permute),
+            "reflect" | "reflect_horizontal" => (1, true),
+            _ => {
+                let name = name.strip_prefix("rotate")?;
+                let (count, reflect) = match name.strip_suffix("reflect") {
+                    Some(count) => (count, true),
+                    None => (name, false),
+                };
+                (count.parse().ok()?, reflect)
+            }
+        };
+        if rotations == 0 || ring % rotations != 0 {
+            return None;
+        }
+        let mut group = vec![];
+        for r in 0..rotations {
+            let rotation: Vec<usize> = identity
+                .iter()
+                .map(|&i| (i + r * ring / rotations) % ring)
+                .collect();
+            if reflect {
+                group.push(mirror(&rotation));
+            }
+            group.push(rotation);
+        }
+        Some(Self::Group(group))
+    }
+
+    /// Returns every rearrangement of `ring`, without repeats.
+    fn apply(&self, ring: &[States]) -> Vec<Vec<States>> {
+        match self {
+            Self::Group(group) => group
+                .iter()
+                .map(|p| p.iter().map(|&i| ring[i]).collect())
+                .collect(),
+            Self::Permute => {
+                let mut ring = ring.to_vec();
+                ring.sort_unstable();
+                let mut rings = vec![ring.clone()];
+                while next_permutation(&mut ring) {
+                    rings.push(ring.clone());
+                }
+                rings
+            }
+        }
+    }
+}
+
+/// Rearranges `items` into the next permutation in lexicographic order,
+/// returning `false` if they were already in the last one.
+fn next_permutation<T: Ord>(items: &mut [T]) -> bool {
+    let i = match items.windows(2).rposition(|pair| pair[0] < pair[1]) {
+        Some(i) => i,
+        None => return false,
+    };
+    let j = items
+        .iter()
+        .rposition(|item| *item > items[i])
+        .expect("a larger item exists");
+    items.swap(i, j);
+    items[i + 1..].reverse();
+    true
+}
+
+/// An entry of a transition.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Entry<'a> {
+    States(States),
+    Var(&'a str),
+}
+
+/// A transition with every variable replaced by its states, where a cell whose
+/// neighborhood is in `inputs` becomes `output`. The inputs are in the order
+/// of the table.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct Transition {
+    inputs: Vec<States>,
+    output: u8,
+}
+
+/// The header and variables of a rule table read so far.
+struct Table<'a> {
+    states: Option<u16>,
+    neighborhood: TableNeighborhood,
+    symmetry: Symmetry,
+    vars: FnvHashMap<&'a str, States>,
+}
+
+impl<'a> Table<'a> {
+    /// Reads a state, a variable or a list of them in braces.
+    fn read_states(&self, text: &str) -> Option<States> {
+        let text = text.trim();
+        if let Some(list) = text
+            .strip_prefix('{')
+            .and_then(|list| list.strip_suffix('}'))
+        {
+            return list.split(',').try_fold(States::EMPTY, |states, item| {
+                Some(states.union(self.read_states(item)?))
+            });
+        }
+        match text.parse::<u8>() {
+            Ok(state) if u16::from(state) < self.states? => Some(States::single(state)),
+            Ok(_) => None,
+            Err(_) => self.vars.get(text).copied(),
+        }
+    }
+
+    /// Reads a transition, expanding the bound variables and symmetries.
+    fn read_transition(&self, line: &'a str) -> Option<Vec<Transition>> {
+        let texts: Vec<&str> = if line.contains([',', '{']) {
+            split_entries(line)
+        } else {
+            // The compact form, with a single digit for each entry.
+            (0..line.len())
+                .map(|i| line.get(i..i + 1))
+                .collect::<Option<_>>()?
+        };
+        let ring = self.neighborhood.ring();
+        if texts.len() != ring + 2 {
+            return None;
+        }
+        let entries = texts
+            .iter()
+            .map(|text| {
+                let text = text.trim();
+                if self.vars.contains_key(text) {
+                    Some(Entry::Var(text))
+                } else {
+                    self.read_states(text).map(Entry::States)
+                }
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        // A variable that appears more than once has the same state
+        // everywhere, so the transition is repeated for each of its states.
+        let mut bound: Vec<&str> = vec![];
+        for (i, entry) in entries.iter().enumerate() {
+            if let Entry::Var(name) = *entry {
+                let repeated = entries[i + 1..].contains(entry);
+                if repeated && !bound.contains(&name) {
+                    bound.push(name);
+                }
+            }
+        }
+        let (output, inputs) = entries.split_last().expect("entries aren't empty");
+        match output {
+            Entry::Var(name) if !bound.contains(name) => return None,
+            Entry::States(states) if states.iter().count() != 1 => return None,
+            _ => {}
+        }
+
+        let mut bindings: Vec<Vec<(&str, u8)>> = vec![vec![]];
+        for &name in &bound {
+            bindings = bindings
+                .into_iter()
+                .flat_map(|binding| {
+                    self.vars[name].iter().map(move |state| {
+                        let mut binding = binding.clone();
+                        binding.push((name, state));
+                        binding
+                    })
+                })
+                .collect();
+        }
+
+        let mut seen = FnvHashSet::default();
+        let mut transitions = vec![];
+        for binding in bindings {
+            let resolve = |entry: &Entry| match *entry {
+                Entry::States(states) => states,
+                Entry::Var(name) => match binding.iter().find(|&&(bound, _)| bound == name) {
+                    Some(&(_, state)) => States::single(state),
+                    None => self.vars[name],
+                },
+            };
+            let inputs: Vec<States> = inputs.iter().map(resolve).collect();
+            let output = resolve(output)
+                .iter()
+                .next()
+                .expect("output is a single state");
+            for ring in self.symmetry.apply(&inputs[1..]) {
+                let mut inputs = vec![inputs[0]];
+                inputs.extend(ring);
+                let transition = Transition { inputs, output };
+                if seen.insert(transition.clone()) {
+                    transitions.push(transition);
+                }
+            }
+        }
+        Some(transitions)
+    }
+}
+
+/// Splits a transition at the commas outside of braces.
+fn split_entries(line: &str) -> Vec<&str> {
+    let (mut entries, mut start, mut depth) = (vec![], 0, 0);
+    for (i, c) in line.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            ',' if depth == 0 => {
+                entries.push(&line[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    entries.push(&line[start..]);
+    entries
+}
+
+/// Compiles the lines of the `@TABLE` section of a rule file, given with their
+/// numbers, into the nodes of a rule tree. Returns the number of states, the
+/// number of neighbors of the tree and its nodes, with the root last.
+///
+/// Each cell takes the output of the first transition that matches its
+/// neighborhood, and stays the same if none do.
+pub(crate) fn compile_table<'a>(
+    lines: impl IntoIterator<Item = (usize, &'a str)>,
+) -> Result<(u16, u8, Vec<TreeNode>), RuleError> {
+    let mut table = Table {
+        states: None,
+        neighborhood: TableNeighborhood::Moore,
+        symmetry: Symmetry::Group(vec![(0..8).collect()]),
+        vars: FnvHashMap::default(),
+    };
+    let mut transitions = vec![];
+    for (number, line) in lines {
+        let invalid = || RuleError::InvalidLine(number);
+        if let Some(var) = line.strip_prefix("var ") {
+            let (name, states) = var.split_once('=').ok_or_else(invalid)?;
+            let states = table.read_states(states).ok_or_else(invalid)?;
+            table.vars.insert(name.trim(), states);
+        } else if let Some((key, value)) = line.split_once(':') {
+            let value = value.trim();
+            match key.trim() {
+                "n_states" => match value.parse() {
+                    Ok(states) if (2..=256).contains(&states) => table.states = Some(states),
+                    Ok(states) => return Err(RuleError::InvalidStates(states)),
+                    Err(_) => return Err(invalid()),
+                },
+                "neighborhood" => {
+                    table.neighborhood = match value {
+                        "Moore" => TableNeighborhood::Moore,
+                        "vonNeumann" => TableNeighborhood::VonNeumann,
+                        "hexagonal" => TableNeighborhood::Hexagonal,
+                        _ => return Err(invalid()),
+                    };
+                    table.symmetry = Symmetry::new("none", table.neighborhood.ring())
+                        .expect("no symmetry is valid");
+                }
+                "symmetries" => {
+                    table.symmetry =
+                        Symmetry::new(value, table.neighborhood.ring()).ok_or_else(invalid)?;
+                }
+                _ => return Err(invalid()),
+            }
+        } else {
+            transitions.extend(table.read_transition(line).ok_or_else(invalid)?);
+        }
+    }
+
+    let states = table.states.ok_or(RuleError::InvalidFormat)?;
+    let mut compiler = Compiler {
+        transitions: &transitions,
+        entries: table.neighborhood.tree_entries(),
+        states,
+        nodes: vec![],
+        unique: FnvHashMap::default(),
+        memo: FnvHashMap::default(),
+    };
+    compiler.node(0, (0..transitions.len() as u32).collect());
+    Ok((states, table.neighborhood.tree_neighbors(), compiler.nodes))
+}
+
+/// Builds a rule tree from a list of transitions.
+struct Compiler<'a> {
+    transitions: &'a [Transition],
+    entries: &'static [Option<usize>],
+    states: u16,
+    nodes: Vec<TreeNode>,
+    /// The index of every node, so that equal nodes are shared.
+    unique: FnvHashMap<TreeNode, u32>,
+    /// The node for each depth in the tree and list of transitions that can
+    /// still match.
+    memo: FnvHashMap<(usize, Vec<u32>), u32>,
+}
+
+impl Compiler<'_> {
+    /// Returns the node that looks at the cell at `depth` in the order of the
+    /// tree, where only the transitions in `candidates` can still match.
+    fn node(&mut self, depth: usize, candidates: Vec<u32>) -> u32 {
+        let key = (depth, candidates);
+        if let Some(&index) = self.memo.get(&key) {
+            return index;
+        }
+        let (depth, candidates) = key;
+
+        let entry = self.entries[depth];
+        let transitions = self.transitions;
+        let matches = |t: u32, state: u8| {
+            entry.map_or(true, |entry| {
+                transitions[t as usize].inputs[entry].contains(state)
+            })
+        };
+        let is_last = depth + 1 == self.entries.len();
+        let next = (0..self.states)
+            .map(|state| {
+                let state = state as u8;
+                if is_last {
+                    let first = candidates.iter().find(|&&t| matches(t, state));
+                    first
+                        .map_or(state, |&t| transitions[t as usize].output)
+                        .into()
+                } else {
+                    let candidates = candidates
+                        .iter()
+                        .copied()
+                        .filter(|&t| matches(t, state))
+                        .collect();
+                    self.node(depth + 1, candidates)
+                }
+            })
+            .collect();
+
+        let node = TreeNode {
+            level: (self.entries.len() - depth) as u8,
+            next,
+        };
+        let nodes = &mut self.nodes;
+        let index = *self.unique.entry(node).or_insert_with_key(|node| {
+            nodes.push(node.clone());
+            nodes.len() as u32 - 1
+        });
+        self.memo.insert((depth, candidates), index);
+        index
+    }
+}
+
+#[test]
+fn test_symmetries() {
+    let rotate4 = Symmetry::new("rotate4", 8).unwrap();
+    assert_eq!(
+        rotate4,
+        Symmetry::Group(vec![
+            vec![0, 1, 2, 3, 4, 5, 6, 7],
+            vec![2, 3, 4, 5, 6, 7, 0, 1],
+            vec![4, 5, 6, 7, 0, 1, 2, 3],
+            vec![6, 7, 0, 1, 2, 3, 4, 5],
+        ])
+    );
+    match Symmetry::new("rotate8reflect", 8) {
+        Some(Symmetry::Group(group)) => assert_eq!(group.len(), 16),
+        symmetry => panic!("{:?}", symmetry),
+    }
+    assert_eq!(
+        Symmetry::new("reflect", 4),
+        Some(Symmetry::Group(vec![vec![0, 3, 2, 1], vec![0, 1, 2, 3]]))
+    );
+    assert_eq!(
+        Symmetry::new("reflect_horizontal", 8),
+        Symmetry::new("reflect", 8)
+    );
+    assert_eq!(Symmetry::new("rotate8", 4), None);
+    assert_eq!(Symmetry::new("rotate", 4), None);
+    assert_eq!(Symmetry::new("spin", 4), None);
+
+    // Two of the same neighbor among eight can be arranged in 28 ways.
+    let (one, other) = (
+        States::single(1),
+        States::single(0).union(States::single(2)),
+    );
+    let ring = [one, other, other, other, other, one, other, other];
+    assert_eq!(Symmetry::Permute.apply(&ring).len(), 28);
+}
+
+#[test]
+fn test_compile() {
+    use crate::rule::RuleTree;
+
+    // A dead cell between two neighbors in the same state across from each
+    // other takes that state, and any state that has one neighbor in state 2
+    // to the north, east, south or west becomes state 1.
+    let table = "@RULE Between
+@TABLE
+n_states:3
+neighborhood:vonNeumann
+symmetries:rotate4
+var a={0,1,2}
+var b={1,2}
+var c={a}
+var d={a}
+0,b,0,b,0,b
+a,2,c,{0,1},d,1
+";
+    let rule: RuleTree<2> = table.parse().unwrap();
+    assert_eq!(rule.neighbors(), 4);
+    assert_eq!(rule.get([[0, 1, 0], [0, 0, 0], [0, 1, 0]]), 1);
+    assert_eq!(rule.get([[0, 0, 0], [2, 0, 2], [0, 0, 0]]), 2);
+    assert_eq!(rule.get([[0, 0, 0], [2, 0, 1], [0, 0, 0]]), 1);
+    assert_eq!(rule.get([[0, 0, 0], [1, 0, 1], [0, 2, 0]]), 1);
+    assert_eq!(rule.get([[0, 0, 0], [0, 2, 0], [0, 2, 0]]), 1);
+    assert_eq!(rule.get([[2, 0, 0], [0, 2, 0], [0, 0, 2]]), 2);
+    assert_eq!(rule.get([[0, 2, 0], [0, 2, 0], [0, 2, 0]]), 2);
+
+    // The compact form and a hexagonal table, which ignores the northeast and
+    // southwest neighbors.
+    let table = "@RULE Hex
+@TABLE
+n_states:2
+neighborhood:hexagonal
+symmetries:rotate6
+01000001
+";
+    let rule: RuleTree<1> = table.parse().unwrap();
+    assert_eq!(rule.neighbors(), 8);
+    assert_eq!(rule.get([[1, 0, 0], [0, 0, 0], [0, 0, 0]]), 1);
+    assert_eq!(rule.get([[0, 0, 1], [0, 0, 0], [0, 0, 0]]), 0);
+    assert_eq!(rule.get([[0, 0, 0], [0, 0, 0], [1, 0, 0]]), 0);
+    assert_eq!(rule.get([[0, 0, 0], [0, 0, 0], [0, 0, 1]]), 1);
+    assert_eq!(rule.get([[0, 1, 0], [0, 0, 0], [0, 0, 1]]), 0);
+
+    // Golly's name for the mirror image from west to east.
+    let table = "@RULE Mirror
+@TABLE
+n_states:2
+neighborhood:Moore
+symmetries:reflect_horizontal
+0,0,1,0,0,0,0,0,0,1
+";
+    let rule: RuleTree<1> = table.parse().unwrap();
+    assert_eq!(rule.get([[0, 0, 1], [0, 0, 0], [0, 0, 0]]), 1);
+    assert_eq!(rule.get([[1, 0, 0], [0, 0, 0], [0, 0, 0]]), 1);
+    assert_eq!(rule.get([[0, 0, 0], [0, 0, 0], [0, 0, 1]]), 0);
+
+    let invalid = |line: &str| {
+        let table = format!("@RULE X\n@TABLE\nn_states:3\n{}\n", line);
+        table.parse::<RuleTree>()
+    };
+    assert!(invalid("0,1,2,0,1,0,1,2,0,1").is_ok());
+    assert_eq!(
+        invalid("0,1,2,0,1,0,1,2,0,3"),
+        Err(RuleError::InvalidLine(4))
+    );
+    assert_eq!(invalid("0,1,2,0,1,0,1,2,1"), Err(RuleError::InvalidLine(4)));
+    assert_eq!(
+        invalid("var a={0,1}\n0,0,0,0,0,0,0,0,0,a"),
+        Err(RuleError::InvalidLine(5))
+    );
+    assert_eq!(
+        invalid("0,1,2,0,1,0,1,2,0,{1,2}"),
+        Err(RuleError::InvalidLine(4))
+    );
+    assert_eq!(
+        invalid("symmetries:rotate3"),
+        Err(RuleError::InvalidLine(4))
+    );
+    assert_eq!(invalid("n_states:300"), Err(RuleError::InvalidStates(300)));
+}
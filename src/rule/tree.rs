@@ -0,0 +1,479 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::leaf::MultiLeaf;
+use crate::life::{Phase, Rule};
+use crate::rule::transitions::compile_table;
+use crate::rule::RuleError;
+use crate::util::Grid2;
+
+use std::fmt;
+use std::simd::u16x16;
+use std::str::FromStr;
+
+/// The cells that a rule tree on the Moore neighborhood looks at, from the
+/// root down, as the row and column in a 3 by 3 window. This is the order
+/// Golly uses: the corners, then the orthogonal neighbors and then the cell
+/// itself.
+const MOORE_ORDER: [(usize, usize); 9] = [
+    (0, 0),
+    (0, 2),
+    (2, 0),
+    (2, 2),
+    (0, 1),
+    (1, 0),
+    (1, 2),
+    (2, 1),
+    (1, 1),
+];
+
+/// The cells that a rule tree on the von Neumann neighborhood looks at, like
+/// `MOORE_ORDER`.
+const VON_NEUMANN_ORDER: [(usize, usize); 5] = [(0, 1), (1, 0), (1, 2), (2, 1), (1, 1)];
+
+/// A node of a rule tree as written in a `@TREE` section: its level, and for
+/// every state of the cell it looks at, the index of the next node or, at
+/// level 1, the next state.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct TreeNode {
+    pub(crate) level: u8,
+    pub(crate) next: Vec<u32>,
+}
+
+/// A rule given by a decision tree over the states of the cells in a Moore
+/// or von Neumann neighborhood, which can have up to 256 states.
+///
+/// Parses from the `.rule` files of Golly. The rule is taken from the `@TREE`
+/// section if there is one, and otherwise from the `@TABLE` section, whose
+/// transitions are compiled into a tree. The name comes from the `@RULE` line
+/// and every other section is ignored. Displays as a `.rule` file with a
+/// `@TREE` section.
+///
+/// The states are stored in `N` bit-planes, so a rule can have at most `2^N`
+/// states. An empty neighborhood must stay empty. Cells in states beyond
+/// the states of the rule, which leaves can still hold, are treated as
+/// empty when the rule is run.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct RuleTree<const N: usize = 8> {
+    name: String,
+    states: u16,
+    neighbors: u8,
+    /// The level of every node.
+    levels: Vec<u8>,
+    /// The nodes flattened into one list, the way Golly evaluates them. Node
+    /// `i` is the `states` entries from `i * states` onwards, which are the
+    /// next states for nodes at level 1 and the offsets of the next nodes
+    /// otherwise.
+    entries: Vec<u32>,
+    root: u32,
+}
+
+impl<const N: usize> RuleTree<N> {
+    /// Creates the rule named `name` from the nodes of a tree, with the root
+    /// last.
+    pub(crate) fn from_nodes(
+        name: &str,
+        states: u16,
+        neighbors: u8,
+        nodes: &[TreeNode],
+    ) -> Result<Self, RuleError> {
+        if states < 2 || u32::from(states) > 1 << N.min(8) {
+            return Err(RuleError::InvalidStates(states));
+        }
+        let top = match neighbors {
+            4 => VON_NEUMANN_ORDER.len() as u8,
+            8 => MOORE_ORDER.len() as u8,
+            _ => return Err(RuleError::InvalidFormat),
+        };
+        let mut entries = Vec::with_capacity(nodes.len() * usize::from(states));
+        for (i, node) in nodes.iter().enumerate() {
+            if node.next.len() != usize::from(states) || !(1..=top).contains(&node.level) {
+                return Err(RuleError::InvalidFormat);
+            }
+            for &next in &node.next {
+                // Nodes only point at earlier nodes one level down.
+                let is_valid = if node.level == 1 {
+                    next < u32::from(states)
+                } else {
+                    (next as usize) < i && nodes[next as usize].level == node.level - 1
+                };
+                if !is_valid {
+                    return Err(RuleError::InvalidFormat);
+                }
+                entries.push(if node.level == 1 {
+                    next
+                } else {
+                    next * u32::from(states)
+                });
+            }
+        }
+        match nodes.last() {
+            Some(root) if root.level == top => {}
+            _ => return Err(RuleError::InvalidFormat),
+        }
+
+        let tree = Self {
+            name: name.to_owned(),
+            states,
+            neighbors,
+            levels: nodes.iter().map(|node| node.level).collect(),
+            entries,
+            root: (nodes.len() as u32 - 1) * u32::from(states),
+        };
+        if tree.get([[0; 3]; 3]) != 0 {
+            return Err(RuleError::UnsupportedB0);
+        }
+        Ok(tree)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn states(&self) -> u16 {
+        self.states
+    }
+
+    /// Returns the number of neighbors that the rule looks at, which is 8 for
+    /// the Moore neighborhood and 4 for the von Neumann neighborhood.
+    pub fn neighbors(&self) -> u8 {
+        self.neighbors
+    }
+
+    fn order(&self) -> &'static [(usize, usize)] {
+        if self.neighbors == 4 {
+            &VON_NEUMANN_ORDER
+        } else {
+            &MOORE_ORDER
+        }
+    }
+
+    /// Returns the next state of the cell in the middle of `window`, which
+    /// holds the states of its neighborhood row by row from the northwest.
+    /// The corners are ignored for the von Neumann neighborhood.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a state is not less than the number of states.
+    pub fn get(&self, window: [[u8; 3]; 3]) -> u8 {
+        let states = self.states;
+        self.order().iter().fold(self.root, |offset, &(row, col)| {
+            let state = window[row][col];
+            assert!(u16::from(state) < states, "invalid state {}", state);
+            self.entries[offset as usize + usize::from(state)]
+        }) as u8
+    }
+
+    /// Advances a square of cells by one generation, with dead cells beyond
+    /// its edges. Cells in states that the rule doesn't have count as dead.
+    fn tick<const S: usize>(&self, cells: &[[u8; S]; S]) -> [[u8; S]; S] {
+        let get = |row: usize, col: usize, dr: usize, dc: usize| {
+            let (row, col) = ((row + dr).checked_sub(1), (col + dc).checked_sub(1));
+            match (row, col) {
+                (Some(row), Some(col)) if row < S && col < S => {
+                    let state = cells[row][col];
+                    if u16::from(state) < self.states {
+                        state
+                    } else {
+                        0
+                    }
+                }
+                _ => 0,
+            }
+        };
+        let mut next = [[0; S]; S];
+        for (row, states) in next.iter_mut().enumerate() {
+            for (col, state) in states.iter_mut().enumerate() {
+                let window = [0, 1, 2].map(|dr| [0, 1, 2].map(|dc| get(row, col, dr, dc)));
+                *state = self.get(window);
+            }
+        }
+        next
+    }
+}
+
+/// Returns the state of every cell of a leaf, row by row from the northwest.
+fn to_states<const N: usize>(leaf: MultiLeaf<N>) -> [[u8; 16]; 16] {
+    let mut states = [[0; 16]; 16];
+    for (row, states) in states.iter_mut().enumerate() {
+        for (col, state) in states.iter_mut().enumerate() {
+            for (i, plane) in leaf.planes.iter().enumerate() {
+                *state |= ((plane[row] >> (15 - col) & 1) as u8) << i;
+            }
+        }
+    }
+    states
+}
+
+fn from_states<const N: usize>(states: &[[u8; 16]; 16]) -> MultiLeaf<N> {
+    let mut planes = [u16x16::splat(0); N];
+    for (i, plane) in planes.iter_mut().enumerate() {
+        for (row, states) in states.iter().enumerate() {
+            plane[row] = states
+                .iter()
+                .fold(0, |bits, &state| bits << 1 | u16::from(state >> i & 1));
+        }
+    }
+    MultiLeaf::new(planes)
+}
+
+impl<const N: usize> Rule for RuleTree<N> {
+    type Leaf = MultiLeaf<N>;
+
+    fn next_background(&self, _: bool) -> bool {
+        // An empty neighborhood stays empty.
+        false
+    }
+
+    fn tick_leaf(&self, leaf: MultiLeaf<N>, _: Phase, _: Phase) -> MultiLeaf<N> {
+        from_states(&self.tick(&to_states(leaf)))
+    }
+
+    fn step_square(&self, leaves: Grid2<MultiLeaf<N>>, _: Phase, ticks: u8) -> MultiLeaf<N> {
+        let mut square = [[0; 32]; 32];
+        let quarters = [(leaves.nw, 0, 0), (leaves.ne, 0, 16)];
+        let quarters = quarters
+            .into_iter()
+            .chain([(leaves.sw, 16, 0), (leaves.se, 16, 16)]);
+        for (leaf, top, left) in quarters {
+            for (row, states) in to_states(leaf).iter().enumerate() {
+                square[top + row][left..left + 16].copy_from_slice(states);
+            }
+        }
+        for _ in 0..ticks {
+            square = self.tick(&square);
+        }
+        let mut center = [[0; 16]; 16];
+        for (row, states) in center.iter_mut().enumerate() {
+            states.copy_from_slice(&square[row + 8][8..24]);
+        }
+        from_states(&center)
+    }
+}
+
+/// Reads the `@TREE` section of a rule file, given as its lines and their
+/// numbers.
+fn read_tree<'a, const N: usize>(
+    name: &str,
+    lines: impl IntoIterator<Item = (usize, &'a str)>,
+) -> Result<RuleTree<N>, RuleError> {
+    let (mut states, mut neighbors, mut count) = (None, None, None);
+    let mut nodes = vec![];
+    let mut last_line = 0;
+    for (number, line) in lines {
+        last_line = number;
+        let invalid = || RuleError::InvalidLine(number);
+        if let Some((key, value)) = line.split_once('=') {
+            let value = value.trim();
+            match key.trim() {
+                "num_states" => states = Some(value.parse().map_err(|_| invalid())?),
+                "num_neighbors" => neighbors = Some(value.parse().map_err(|_| invalid())?),
+                "num_nodes" => count = Some(value.parse().map_err(|_| invalid())?),
+                _ => return Err(invalid()),
+            }
+            continue;
+        }
+        let mut values = line.split_whitespace().map(|value| value.parse::<u32>());
+        let level = values.next().and_then(Result::ok).ok_or_else(invalid)?;
+        let next = values.collect::<Result<_, _>>().map_err(|_| invalid())?;
+        let level = u8::try_from(level).map_err(|_| invalid())?;
+        nodes.push(TreeNode { level, next });
+    }
+
+    let invalid = RuleError::InvalidLine(last_line);
+    let (states, neighbors) = states.zip(neighbors).ok_or_else(|| invalid.clone())?;
+    if count.map_or(false, |count: usize| count != nodes.len()) {
+        return Err(invalid);
+    }
+    RuleTree::from_nodes(name, states, neighbors, &nodes)
+}
+
+impl<const N: usize> FromStr for RuleTree<N> {
+    type Err = RuleError;
+
+    fn from_str(file: &str) -> Result<Self, Self::Err> {
+        let mut name = None;
+        let (mut table, mut tree) = (None, None);
+        let mut section: Option<&mut Vec<(usize, &str)>> = None;
+        for (number, line) in (1..).zip(file.lines()) {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(header) = line.strip_prefix('@') {
+                section = None;
+                let (header, rest) = header.split_once(' ').unwrap_or((header, ""));
+                match header {
+                    "RULE" => name = Some(rest.trim()),
+                    "TABLE" => section = Some(table.insert(vec![])),
+                    "TREE" => section = Some(tree.insert(vec![])),
+                    _ => {}
+                }
+            } else if let Some(lines) = &mut section {
+                lines.push((number, line));
+            }
+        }
+
+        let name = name.ok_or(RuleError::InvalidFormat)?;
+        match (tree, table) {
+            (Some(tree), _) => read_tree(name, tree),
+            (None, Some(table)) => {
+                let (states, neighbors, nodes) = compile_table(table)?;
+                Self::from_nodes(name, states, neighbors, &nodes)
+            }
+            (None, None) => Err(RuleError::InvalidFormat),
+        }
+    }
+}
+
+impl<const N: usize> fmt::Display for RuleTree<N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "@RULE {}", self.name)?;
+        writeln!(f)?;
+        writeln!(f, "@TREE")?;
+        writeln!(f)?;
+        writeln!(f, "num_states={}", self.states)?;
+        writeln!(f, "num_neighbors={}", self.neighbors)?;
+        writeln!(f, "num_nodes={}", self.levels.len())?;
+        let states = u32::from(self.states);
+        let nodes = self.entries.chunks(usize::from(self.states));
+        for (&level, entries) in self.levels.iter().zip(nodes) {
+            write!(f, "{}", level)?;
+            for &entry in entries {
+                let next = if level == 1 { entry } else { entry / states };
+                write!(f, " {}", next)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// WireWorld as a rule table, where 0 is empty, 1 is an electron head, 2 is
+/// an electron tail and 3 is a conductor.
+#[cfg(test)]
+const WIREWORLD: &str = "@RULE WireWorld
+
+@TABLE
+n_states:4
+neighborhood:Moore
+symmetries:permute
+
+var a={0,1,2,3}
+var b={a}
+var c={a}
+var d={a}
+var e={a}
+var f={a}
+var g={a}
+var h={a}
+var i={0,2,3}
+var j={i}
+var k={i}
+var l={i}
+var m={i}
+var n={i}
+var o={i}
+
+1,a,b,c,d,e,f,g,h,2  # a head becomes a tail
+2,a,b,c,d,e,f,g,h,3  # a tail becomes a conductor
+# A conductor becomes a head next to one or two heads.
+3,1,i,j,k,l,m,n,o,1
+3,1,1,i,j,k,l,m,n,1
+
+@COLORS
+1 255 255 255
+";
+
+#[test]
+fn test_parse() {
+    let wireworld: RuleTree<2> = WIREWORLD.parse().unwrap();
+    assert_eq!(wireworld.name(), "WireWorld");
+    assert_eq!((wireworld.states(), wireworld.neighbors()), (4, 8));
+    assert_eq!(wireworld.get([[1, 0, 0], [0, 3, 0], [0, 0, 0]]), 1);
+    assert_eq!(wireworld.get([[1, 0, 1], [0, 3, 0], [0, 0, 0]]), 1);
+    assert_eq!(wireworld.get([[1, 0, 1], [0, 3, 0], [0, 1, 0]]), 3);
+    assert_eq!(wireworld.get([[2, 2, 2], [3, 3, 3], [0, 0, 0]]), 3);
+    assert_eq!(wireworld.get([[2, 2, 2], [3, 1, 3], [0, 0, 0]]), 2);
+    assert_eq!(wireworld.get([[1, 1, 1], [1, 0, 1], [1, 1, 1]]), 0);
+
+    // Writing the tree and reading it back gives the same rule.
+    let tree = wireworld.to_string();
+    assert!(tree.starts_with("@RULE WireWorld\n\n@TREE\n\nnum_states=4\n"));
+    assert_eq!(tree.parse(), Ok(wireworld));
+
+    // A rule tree written by hand, where a cell becomes the state of its
+    // north neighbor.
+    let north = "@RULE North\n@TREE\nnum_states=3\nnum_neighbors=4\nnum_nodes=13\n\
+        1 0 0 0\n1 1 1 1\n1 2 2 2\n2 0 0 0\n2 1 1 1\n2 2 2 2\n\
+        3 3 3 3\n3 4 4 4\n3 5 5 5\n4 6 6 6\n4 7 7 7\n4 8 8 8\n5 9 10 11\n";
+    let north: RuleTree<2> = north.parse().unwrap();
+    assert_eq!(north.neighbors(), 4);
+    assert_eq!(north.get([[2, 2, 0], [1, 1, 1], [0, 1, 2]]), 2);
+    assert_eq!(north.get([[0, 1, 0], [2, 0, 2], [2, 0, 2]]), 1);
+
+    let root_too_low = "@RULE Low\n@TREE\nnum_states=2\nnum_neighbors=4\n1 0 1\n";
+    assert_eq!(
+        root_too_low.parse::<RuleTree>(),
+        Err(RuleError::InvalidFormat)
+    );
+    assert_eq!(
+        WIREWORLD.parse::<RuleTree<1>>(),
+        Err(RuleError::InvalidStates(4))
+    );
+    assert_eq!(
+        "@TABLE\nn_states:2\n".parse::<RuleTree>(),
+        Err(RuleError::InvalidFormat)
+    );
+    assert_eq!(
+        WIREWORLD
+            .replace("var o={i}", "var o={q}")
+            .parse::<RuleTree>(),
+        Err(RuleError::InvalidLine(22))
+    );
+    assert_eq!(
+        WIREWORLD.replace("1,a,b,c", "0,a,b,c").parse::<RuleTree>(),
+        Err(RuleError::UnsupportedB0)
+    );
+}
+
+#[test]
+fn test_evolve() {
+    use crate::life::Universe;
+    use crate::rule::check_evolve;
+
+    // Mostly conductors, so that electrons have somewhere to go.
+    let mut universe = Universe::with_rule(WIREWORLD.parse::<RuleTree<2>>().unwrap());
+    check_evolve(&mut universe, 96, [3, 3, 1, 0], 1, 40, |_, _, get| {
+        let heads = (-1..=1)
+            .flat_map(|dy| (-1..=1).map(move |dx| (dx, dy)))
+            .filter(|&(dx, dy)| get(dx, dy) == 1)
+            .count();
+        match get(0, 0) {
+            1 => 2,
+            2 => 3,
+            3 if heads == 1 || heads == 2 => 1,
+            state => state,
+        }
+    });
+}
+
+#[test]
+fn test_invalid_states() {
+    use crate::life::Universe;
+    use crate::pattern::read_rle;
+    use crate::util::Vec2;
+
+    // Leaves with three bit-planes can hold states that WireWorld doesn't
+    // have. Cells in those states die, and don't count as electron heads.
+    let mut universe = Universe::with_rule(WIREWORLD.parse::<RuleTree<3>>().unwrap());
+    let (root, _) = read_rle("x = 3, y = 1\nECE!".as_bytes(), universe.nodes_mut()).unwrap();
+    let result = universe.evolve(root, 1).unwrap();
+    let mut cells = vec![];
+    universe
+        .nodes()
+        .for_each_cell(result, |cell, state| cells.push((cell, state)))
+        .unwrap();
+    assert_eq!(cells, [(Vec2::new(0, 0), 3)]);
+}
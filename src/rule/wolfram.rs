@@ -0,0 +1,314 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::life::{LifeRule, NodeError, NodeId, Universe};
+use crate::rule::{count_equals, neighbor_counts, RuleError};
+use crate::util::{BitGrid, Dir, Vec2};
+
+use std::fmt;
+use std::str::FromStr;
+
+/// A one-dimensional rule, run on the plane as its spacetime diagram, where
+/// each row holds the generation after the row to its north.
+///
+/// An elementary rule is given by its Wolfram number, where bit `4l + 2c + r`
+/// is the next state of a cell whose left neighbor, itself and right neighbor
+/// are `l`, `c` and `r`. A totalistic rule with radius `r` looks at the `2r +
+/// 1` cells around a cell, and bit `n` of its code is the next state when `n`
+/// of them are alive. Rules where a row of dead cells becomes alive, which
+/// are those with bit 0 set, aren't supported.
+///
+/// On the plane, a cell becomes alive when the cells to its northwest, north
+/// and northeast would turn it alive in the one-dimensional rule, and stays
+/// alive forever. Starting from a single row, each generation fills in the
+/// next row of the diagram and leaves the rows above it unchanged, so `t`
+/// generations later the `t` rows below the first are filled in. See
+/// `Universe::spacetime`.
+///
+/// Parses from `W110` for an elementary rule, as in Golly, and from `W20T`
+/// for a totalistic rule, which may start with `R2,` for a radius other than
+/// 1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Wolfram {
+    code: u16,
+    radius: u8,
+    totalistic: bool,
+}
+
+impl Wolfram {
+    /// Creates the elementary rule with the Wolfram number `number`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `number` is odd.
+    pub fn elementary(number: u8) -> Self {
+        assert!(number & 1 == 0, "odd rules aren't supported");
+        Self {
+            code: number.into(),
+            radius: 1,
+            totalistic: false,
+        }
+    }
+
+    /// Creates the totalistic rule with the code `code`, counting the alive
+    /// cells within `radius` of a cell.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `code` is odd or has bits set beyond the largest count, or if
+    /// `radius` isn't between 1 and 7.
+    pub fn totalistic(code: u16, radius: u8) -> Self {
+        assert!(
+            (1..=7).contains(&radius),
+            "radius {} is out of range",
+            radius
+        );
+        assert!(code & 1 == 0, "odd codes aren't supported");
+        assert!(
+            u32::from(code) >> (2 * radius + 2) == 0,
+            "code {} is out of range",
+            code
+        );
+        Self {
+            code,
+            radius,
+            totalistic: true,
+        }
+    }
+
+    /// Returns whether the rule only depends on the number of alive cells.
+    pub fn is_totalistic(&self) -> bool {
+        self.totalistic
+    }
+
+    /// Returns the next state of a cell in the one-dimensional rule, given the
+    /// cells within the radius of it from left to right.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are not `2 * radius + 1` cells.
+    pub fn get(&self, cells: &[bool]) -> bool {
+        assert_eq!(cells.len(), 2 * usize::from(self.radius) + 1);
+        let index = if self.totalistic {
+            cells.iter().filter(|&&alive| alive).count()
+        } else {
+            cells
+                .iter()
+                .fold(0, |index, &alive| index << 1 | usize::from(alive))
+        };
+        self.code >> index & 1 == 1
+    }
+}
+
+impl LifeRule for Wolfram {
+    fn tick<B: BitGrid>(&self, a: B) -> B {
+        // The cells of the row to the north, from the west to the east.
+        let north = a.shift(Dir::South);
+        let mut row = vec![north];
+        let (mut west, mut east) = (north, north);
+        for _ in 0..self.radius {
+            west = west.shift(Dir::East);
+            east = east.shift(Dir::West);
+            row.insert(0, west);
+            row.push(east);
+        }
+
+        let zero = a & !a;
+        let mut born = zero;
+        if self.totalistic {
+            let count = neighbor_counts(&row);
+            for n in (0..=row.len() as u8).filter(|&n| self.code >> n & 1 == 1) {
+                born = born | count_equals(count, n);
+            }
+        } else {
+            for index in (0..8).filter(|&index| self.code >> index & 1 == 1) {
+                let bits = [4, 2, 1].map(|bit| index & bit != 0);
+                let cells = row.iter().zip(bits);
+                born = born
+                    | cells.fold(!zero, |born, (&cell, alive)| {
+                        born & if alive { cell } else { !cell }
+                    });
+            }
+        }
+        a | born
+    }
+
+    fn radius(&self) -> u8 {
+        self.radius
+    }
+}
+
+impl FromStr for Wolfram {
+    type Err = RuleError;
+
+    fn from_str(rule: &str) -> Result<Self, Self::Err> {
+        let rule = rule.trim();
+        let (radius, rule) = match rule.split_once(',') {
+            Some((radius, rule)) => (Some(radius), rule),
+            None => (None, rule),
+        };
+        let rule = rule
+            .strip_prefix(['W', 'w'])
+            .ok_or(RuleError::InvalidFormat)?;
+        let (code, totalistic) = match rule.strip_suffix(['T', 't']) {
+            Some(code) => (code, true),
+            None => (rule, false),
+        };
+        let code: u16 = code.parse().map_err(|_| RuleError::InvalidFormat)?;
+        let radius = match radius {
+            Some(radius) if totalistic => radius
+                .strip_prefix(['R', 'r'])
+                .and_then(|radius| radius.parse().ok())
+                .filter(|radius| (1..=7).contains(radius))
+                .ok_or(RuleError::InvalidFormat)?,
+            Some(_) => return Err(RuleError::InvalidFormat),
+            None => 1,
+        };
+
+        let max_bits = if totalistic { 2 * radius + 2 } else { 8 };
+        if u32::from(code) >> max_bits != 0 {
+            return Err(RuleError::InvalidFormat);
+        }
+        if code & 1 == 1 {
+            return Err(RuleError::UnsupportedB0);
+        }
+        Ok(Self {
+            code,
+            radius,
+            totalistic,
+        })
+    }
+}
+
+impl fmt::Display for Wolfram {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.totalistic && self.radius != 1 {
+            write!(f, "R{},", self.radius)?;
+        }
+        write!(f, "W{}", self.code)?;
+        if self.totalistic {
+            write!(f, "T")?;
+        }
+        Ok(())
+    }
+}
+
+impl Universe<Wolfram> {
+    /// Builds the spacetime diagram of the rule, starting from a row with
+    /// alive cells at the positions `row`.
+    ///
+    /// Generation `t` of the one-dimensional rule is the row at `y = -t`, for
+    /// every generation from 0 to `generations`. The diagram is filled in by
+    /// evolving the first row, so diagrams of rules that repeat themselves
+    /// are cheap even when they are huge.
+    pub fn spacetime(
+        &mut self,
+        row: impl IntoIterator<Item = i64>,
+        generations: u64,
+    ) -> Result<NodeId, NodeError> {
+        let row = self
+            .nodes_mut()
+            .build(row.into_iter().map(|x| Vec2::new(x, 0)));
+        self.evolve(row, generations)
+    }
+}
+
+#[test]
+fn test_parse() {
+    let rule_110 = Wolfram::elementary(110);
+    assert_eq!("W110".parse(), Ok(rule_110));
+    assert_eq!(rule_110.to_string(), "W110");
+    assert!(rule_110.get(&[true, true, false]));
+    assert!(!rule_110.get(&[true, true, true]));
+
+    let totalistic = Wolfram::totalistic(20, 2);
+    assert_eq!("R2,W20T".parse(), Ok(totalistic));
+    assert_eq!(totalistic.to_string(), "R2,W20T");
+    assert!(totalistic.get(&[true, false, true, false, false]));
+    assert!(!totalistic.get(&[true, false, true, false, true]));
+    assert_eq!("w6t".parse(), Ok(Wolfram::totalistic(6, 1)));
+    assert_eq!(Wolfram::totalistic(6, 1).to_string(), "W6T");
+
+    assert_eq!("W111".parse::<Wolfram>(), Err(RuleError::UnsupportedB0));
+    assert_eq!("W256".parse::<Wolfram>(), Err(RuleError::InvalidFormat));
+    assert_eq!("W16T".parse::<Wolfram>(), Err(RuleError::InvalidFormat));
+    assert_eq!("R2,W110".parse::<Wolfram>(), Err(RuleError::InvalidFormat));
+    assert_eq!("R8,W2T".parse::<Wolfram>(), Err(RuleError::InvalidFormat));
+    assert_eq!("B3/S23".parse::<Wolfram>(), Err(RuleError::InvalidFormat));
+}
+
+#[test]
+fn test_spacetime() {
+    use crate::life::soup;
+    use crate::pattern::{read_rle, write_macrocell, write_rle, Metadata};
+
+    // Compare the diagrams against running the rules one row at a time, from
+    // a random row.
+    const GENERATIONS: usize = 100;
+    let run = |rule: Wolfram| {
+        let radius = usize::from(rule.radius);
+        let offset = (GENERATIONS + 1) * radius;
+        let width = 64 + 2 * offset;
+        let mut row = vec![false; width];
+        let bits = soup(u64::from(rule.code), !0)[0];
+        for i in 0..64 {
+            row[offset + i] = bits >> i & 1 == 1;
+        }
+        let x = |i: usize| i as i64 - offset as i64;
+        let mut expected = vec![];
+        for t in 0..=GENERATIONS {
+            let cells = row.iter().enumerate().filter(|&(_, &alive)| alive);
+            expected.extend(cells.map(|(i, _)| (x(i), -(t as i64))));
+            let mut next = vec![false; width];
+            for i in radius..width - radius {
+                next[i] = rule.get(&row[i - radius..=i + radius]);
+            }
+            row = next;
+        }
+        expected.sort_unstable();
+
+        let mut universe = Universe::with_rule(rule);
+        let row = (0..64).filter(|i| bits >> i & 1 == 1);
+        let root = universe.spacetime(row, GENERATIONS as u64).unwrap();
+        let mut cells = vec![];
+        universe
+            .nodes()
+            .for_each_alive(root, |cell| cells.push((cell.x, cell.y)))
+            .unwrap();
+        cells.sort_unstable();
+        assert_eq!(cells, expected, "{}", rule);
+    };
+    for number in [30, 90, 110, 184] {
+        run(Wolfram::elementary(number));
+    }
+    run(Wolfram::totalistic(20, 2));
+    run("R3,W182T".parse().unwrap());
+
+    // Rule 90 from a single cell is a Sierpinski triangle, which is made of
+    // so few distinct nodes that a huge diagram is cheap to build and to
+    // write out.
+    let mut universe = Universe::with_rule(Wolfram::elementary(90));
+    let root = universe.spacetime([0], (1 << 40) - 1).unwrap();
+    let mut macrocell = vec![];
+    write_macrocell(&mut macrocell, universe.nodes(), root, &Metadata::default()).unwrap();
+    assert!(macrocell.len() < 10_000);
+
+    // Smaller diagrams can be written to other formats.
+    let root = universe.spacetime([0], 15).unwrap();
+    let metadata = Metadata {
+        rule: Some(universe.rule().to_string()),
+        ..Metadata::default()
+    };
+    let mut rle = vec![];
+    write_rle(&mut rle, universe.nodes(), root, &metadata).unwrap();
+    let rle = String::from_utf8(rle).unwrap();
+    assert!(rle.starts_with("x = 31, y = 16, rule = W90\n"));
+    let (read, _) = read_rle(rle.as_bytes(), universe.nodes_mut()).unwrap();
+    let mut population = 0;
+    universe
+        .nodes()
+        .for_each_alive(read, |_| population += 1)
+        .unwrap();
+    assert_eq!(population, 81);
+}
@@ -0,0 +1,308 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Bounded grids, whose edges are either dead or joined to other edges.
+
+use crate::rule::RuleError;
+use crate::util::Vec2;
+
+use std::fmt;
+use std::str::FromStr;
+
+/// The shape of the grid that a `Universe` evolves patterns on.
+///
+/// A bounded grid of `width` by `height` cells is centered on the origin in
+/// the same way as a pattern read from an RLE file, so its northwest cell is
+/// at `x = -(width / 2)` and `y = height / 2`.
+///
+/// Parses from the suffixes that Golly adds to rule strings after a `:`, such
+/// as `T100,80` for a torus of 100 by 80 cells. The height may be left out
+/// for a square grid.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Topology {
+    /// The infinite plane.
+    Plane,
+    /// A plane where the cells beyond the edges are always dead, written `P`.
+    BoundedPlane { width: u32, height: u32 },
+    /// A torus, where each edge is joined to the opposite edge, written `T`.
+    Torus { width: u32, height: u32 },
+    /// A Klein bottle, which is a torus where one pair of opposite edges is
+    /// joined with a twist, so that a pattern crossing it is mirrored.
+    ///
+    /// Written `K100*,80` if the north and south edges, which are `width`
+    /// cells long, are twisted, and `K100,80*` if the west and east edges are.
+    KleinBottle {
+        width: u32,
+        height: u32,
+        twisted_width: bool,
+    },
+    /// A cross-surface, where both pairs of opposite edges are joined with a
+    /// twist, written `C`.
+    CrossSurface { width: u32, height: u32 },
+}
+
+impl Topology {
+    /// Returns the width and height of the grid, or `None` for the infinite
+    /// plane.
+    pub fn size(&self) -> Option<(u32, u32)> {
+        match *self {
+            Self::Plane => None,
+            Self::BoundedPlane { width, height }
+            | Self::Torus { width, height }
+            | Self::KleinBottle { width, height, .. }
+            | Self::CrossSurface { width, height } => Some((width, height)),
+        }
+    }
+
+    /// Returns the northwest and southeast corners of the grid, or `None` for
+    /// the infinite plane.
+    pub fn bounds(&self) -> Option<(Vec2, Vec2)> {
+        let (width, height) = self.size()?;
+        let (width, height) = (i64::from(width), i64::from(height));
+        let northwest = Vec2::new(-(width / 2), height / 2);
+        Some((northwest, northwest + Vec2::new(width - 1, 1 - height)))
+    }
+
+    /// Returns `true` if `cell` is on the grid.
+    pub fn contains(&self, cell: Vec2) -> bool {
+        self.bounds().map_or(true, |(northwest, southeast)| {
+            (northwest.x..=southeast.x).contains(&cell.x)
+                && (southeast.y..=northwest.y).contains(&cell.y)
+        })
+    }
+
+    /// Returns the cell on the grid that `cell` stands for, going across the
+    /// joined edges as often as needed, or `None` if `cell` is beyond a dead
+    /// edge.
+    pub fn wrap(&self, cell: Vec2) -> Option<Vec2> {
+        let (northwest, _) = match self.bounds() {
+            Some(bounds) => bounds,
+            None => return Some(cell),
+        };
+        let (twist_north_south, twist_west_east) = match *self {
+            Self::Plane => return Some(cell),
+            Self::BoundedPlane { .. } => return self.contains(cell).then(|| cell),
+            Self::Torus { .. } => (false, false),
+            Self::KleinBottle { twisted_width, .. } => (twisted_width, !twisted_width),
+            Self::CrossSurface { .. } => (true, true),
+        };
+        let (width, height) = self.size().expect("grid is bounded");
+        let (width, height) = (i64::from(width), i64::from(height));
+
+        let (mut col, mut row) = (cell.x - northwest.x, northwest.y - cell.y);
+        // Each time a twisted edge is crossed, the other coordinate is
+        // mirrored.
+        if twist_north_south && row.div_euclid(height) % 2 != 0 {
+            col = width - 1 - col;
+        }
+        row = row.rem_euclid(height);
+        if twist_west_east && col.div_euclid(width) % 2 != 0 {
+            row = height - 1 - row;
+        }
+        col = col.rem_euclid(width);
+        Some(Vec2::new(northwest.x + col, northwest.y - row))
+    }
+}
+
+impl Default for Topology {
+    fn default() -> Self {
+        Self::Plane
+    }
+}
+
+impl FromStr for Topology {
+    type Err = RuleError;
+
+    fn from_str(topology: &str) -> Result<Self, Self::Err> {
+        let topology = topology.trim();
+        let kind = topology.chars().next().ok_or(RuleError::InvalidFormat)?;
+        let size = &topology[kind.len_utf8()..];
+        let (width, height) = size.split_once(',').unwrap_or((size, size));
+        let read = |size: &str| -> Result<(u32, bool), RuleError> {
+            let (size, twisted) = match size.strip_suffix('*') {
+                Some(size) => (size, true),
+                None => (size, false),
+            };
+            match size.parse() {
+                Ok(size) if size > 0 => Ok((size, twisted)),
+                _ => Err(RuleError::InvalidFormat),
+            }
+        };
+        let ((width, twisted_width), (height, twisted_height)) = (read(width)?, read(height)?);
+
+        let kind = kind.to_ascii_uppercase();
+        match (kind, twisted_width, twisted_height) {
+            ('P', false, false) => Ok(Self::BoundedPlane { width, height }),
+            ('T', false, false) => Ok(Self::Torus { width, height }),
+            ('K', true, false) | ('K', false, true) => Ok(Self::KleinBottle {
+                width,
+                height,
+                twisted_width,
+            }),
+            ('C', false, false) => Ok(Self::CrossSurface { width, height }),
+            _ => Err(RuleError::InvalidFormat),
+        }
+    }
+}
+
+impl fmt::Display for Topology {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::Plane => Ok(()),
+            Self::BoundedPlane { width, height } => write!(f, "P{},{}", width, height),
+            Self::Torus { width, height } => write!(f, "T{},{}", width, height),
+            Self::KleinBottle {
+                width,
+                height,
+                twisted_width: true,
+            } => write!(f, "K{}*,{}", width, height),
+            Self::KleinBottle { width, height, .. } => write!(f, "K{},{}*", width, height),
+            Self::CrossSurface { width, height } => write!(f, "C{},{}", width, height),
+        }
+    }
+}
+
+#[test]
+fn test_parse() {
+    let torus = Topology::Torus {
+        width: 100,
+        height: 80,
+    };
+    assert_eq!("T100,80".parse(), Ok(torus));
+    assert_eq!(torus.to_string(), "T100,80");
+    assert_eq!(
+        "p30".parse(),
+        Ok(Topology::BoundedPlane {
+            width: 30,
+            height: 30
+        })
+    );
+    for topology in ["K100*,80", "K100,80*", "C5,7", "P1,2"] {
+        let parsed: Topology = topology.parse().unwrap();
+        assert_eq!(parsed.to_string(), topology);
+    }
+
+    for topology in [
+        "",
+        "T",
+        "T0,80",
+        "T100,x",
+        "K100,80",
+        "K100*,80*",
+        "T100*,80",
+        "X1,1",
+    ] {
+        assert_eq!(
+            topology.parse::<Topology>(),
+            Err(RuleError::InvalidFormat),
+            "{}",
+            topology
+        );
+    }
+}
+
+#[test]
+fn test_wrap() {
+    // A 4 by 3 grid, from (-2, 1) in the northwest to (1, -1) in the
+    // southeast.
+    let (width, height) = (4, 3);
+    let plane = Topology::BoundedPlane { width, height };
+    assert_eq!(plane.bounds(), Some((Vec2::new(-2, 1), Vec2::new(1, -1))));
+    assert_eq!(plane.wrap(Vec2::new(1, -1)), Some(Vec2::new(1, -1)));
+    assert_eq!(plane.wrap(Vec2::new(2, -1)), None);
+
+    let torus = Topology::Torus { width, height };
+    assert_eq!(torus.wrap(Vec2::new(2, 2)), Some(Vec2::new(-2, -1)));
+    assert_eq!(torus.wrap(Vec2::new(-10, 0)), Some(Vec2::new(-2, 0)));
+
+    // Going off the north edge comes back on the south edge, mirrored.
+    let klein = Topology::KleinBottle {
+        width,
+        height,
+        twisted_width: true,
+    };
+    assert_eq!(klein.wrap(Vec2::new(-2, 2)), Some(Vec2::new(1, -1)));
+    assert_eq!(klein.wrap(Vec2::new(-2, 5)), Some(Vec2::new(-2, 2 - 3)));
+    assert_eq!(klein.wrap(Vec2::new(2, 1)), Some(Vec2::new(-2, 1)));
+
+    let cross = Topology::CrossSurface { width, height };
+    assert_eq!(cross.wrap(Vec2::new(2, 1)), Some(Vec2::new(-2, -1)));
+    assert_eq!(cross.wrap(Vec2::new(0, -2)), Some(Vec2::new(-1, 1)));
+}
+
+#[test]
+fn test_glider_on_torus() {
+    use crate::life::Universe;
+
+    // A glider moves one cell diagonally every 4 generations, so on a 10 by
+    // 10 torus it is back where it started after 40.
+    let mut universe: Universe = Universe::new();
+    universe.set_topology("T10".parse().unwrap());
+    let glider = [(0, 1), (1, 0), (-1, -1), (0, -1), (1, -1)];
+    let glider = universe
+        .nodes_mut()
+        .build(glider.map(|(x, y)| Vec2::new(x, y)));
+    let cells = |universe: &Universe, id| {
+        let mut cells = vec![];
+        universe
+            .nodes()
+            .for_each_alive(id, |cell| cells.push((cell.x, cell.y)))
+            .unwrap();
+        cells.sort_unstable();
+        cells
+    };
+
+    let expected = cells(&universe, glider);
+    let mut id = glider;
+    for _ in 0..40 {
+        id = universe.evolve(id, 1).unwrap();
+        assert_eq!(cells(&universe, id).len(), 5);
+    }
+    assert_eq!(cells(&universe, id), expected);
+    let id = universe.evolve(glider, 40).unwrap();
+    assert_eq!(cells(&universe, id), expected);
+
+    // On a larger torus the generations are computed in long blocks, so many
+    // laps are cheap.
+    universe.set_topology("T64".parse().unwrap());
+    let id = universe.evolve(glider, 1 << 16).unwrap();
+    assert_eq!(cells(&universe, id), expected);
+}
+
+#[test]
+fn test_evolve() {
+    use crate::life::{LifeRule, Rule, Universe};
+    use crate::rule::{check_evolve, LargerThanLife};
+
+    // The slow simulation looks up cells beyond the edges with `wrap`.
+    fn run<R: Rule + LifeRule>(rule: R, topology: &str, seed: u64, alive: fn(bool, usize) -> bool) {
+        let radius = i64::from(LifeRule::radius(&rule));
+        let mut universe = Universe::with_rule(rule);
+        universe.set_topology(topology.parse().unwrap());
+        check_evolve(&mut universe, 0, [0, 1, 0, 1], seed, 30, |_, _, get| {
+            let count = (-radius..=radius)
+                .flat_map(|dy| (-radius..=radius).map(move |dx| (dx, dy)))
+                .filter(|&(dx, dy)| (dx, dy) != (0, 0) && get(dx, dy) == 1)
+                .count();
+            u8::from(alive(get(0, 0) == 1, count))
+        });
+    }
+
+    let life = |alive, count| count == 3 || alive && count == 2;
+    for (seed, topology) in ["P13,10", "T13,10", "K13*,10", "K13,10*", "C13,10", "T3,40"]
+        .into_iter()
+        .enumerate()
+    {
+        run(crate::life::B3S23, topology, seed as u64 + 1, life);
+    }
+
+    let rule: LargerThanLife = "R2,C0,M0,S3..8,B5..7".parse().unwrap();
+    let alive = |alive, count| (5..=7).contains(&count) || alive && (3..=8).contains(&count);
+    for (seed, topology) in ["T20,15", "K20*,15", "C15,20", "P20,15"]
+        .into_iter()
+        .enumerate()
+    {
+        run(rule, topology, seed as u64 + 40, alive);
+    }
+}
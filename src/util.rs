@@ -3,7 +3,7 @@
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
 use std::mem::MaybeUninit;
-use std::ops::{BitAnd, BitOr, BitXor, Shl, Shr};
+use std::ops::{BitAnd, BitOr, BitXor, Not, Shl, Shr};
 use std::simd::{LaneCount, Simd, SimdElement, SupportedLaneCount};
 
 use derive_more as dm;
@@ -26,63 +26,94 @@ pub struct Grid2<T> {
 }
 
 impl<T> Grid2<T> {
+    pub(crate) fn repeat(value: T) -> Self
+    where
+        T: Copy,
+    {
+        Self::from_array([value; 4])
+    }
+
     pub(crate) fn map<U>(self, f: impl FnMut(T) -> U) -> Grid2<U> {
         self.to_array().map(f).to_grid()
     }
 
-    pub(crate) fn try_map<U>(self, f: impl FnMut(T) -> Option<U>) -> Option<Grid2<U>> {
-        Some(self.to_array().try_map(f)?.to_grid())
+    pub(crate) fn try_map<U, E>(self, f: impl FnMut(T) -> Result<U, E>) -> Result<Grid2<U>, E> {
+        Ok(self.to_array().try_map(f)?.to_grid())
     }
 }
 
 impl<T: Copy> Grid2<T> {
-    pub fn do_it<U: Copy>(
+    /// Computes the center of a 2x2 grid of squares in two stages.
+    ///
+    /// The nine overlapping sub-squares of the grid are built with `split` and
+    /// `combine`, and each is reduced to its center by `f0`. The nine partial
+    /// results are then combined into four squares which are reduced again by
+    /// `f1`. The context `ctx` is passed to every closure so that they can all
+    /// share mutable state, such as a node arena.
+    pub fn do_it<C, U: Copy>(
         self,
-        mut f0: impl FnMut(T) -> U,
-        mut f1: impl FnMut(T) -> U,
-        mut split: impl FnMut(T) -> Grid2<U>,
-        mut combine: impl FnMut(Grid2<U>) -> T,
+        ctx: &mut C,
+        mut f0: impl FnMut(&mut C, T) -> U,
+        mut f1: impl FnMut(&mut C, T) -> U,
+        mut split: impl FnMut(&mut C, T) -> Grid2<U>,
+        mut combine: impl FnMut(&mut C, Grid2<U>) -> T,
     ) -> T {
-        let grandkids: Grid2<Grid2<U>> = self.to_array().map(&mut split).to_grid();
-
-        let [partial_nw, partial_ne, partial_sw, partial_se] = self.to_array().map(&mut f0);
-        let partial_n = f0(combine(grandkids.north()));
-        let partial_s = f0(combine(grandkids.south()));
-        let partial_e = f0(combine(grandkids.east()));
-        let partial_w = f0(combine(grandkids.west()));
-        let partial_center = f0(combine(grandkids.center()));
-
-        let final_nw = f1(combine(Grid2 {
-            nw: partial_nw,
-            ne: partial_n,
-            sw: partial_w,
-            se: partial_center,
-        }));
-        let final_ne = f1(combine(Grid2 {
-            nw: partial_n,
-            ne: partial_ne,
-            sw: partial_center,
-            se: partial_e,
-        }));
-        let final_sw = f1(combine(Grid2 {
-            nw: partial_w,
-            ne: partial_center,
-            sw: partial_sw,
-            se: partial_s,
-        }));
-        let final_se = f1(combine(Grid2 {
-            nw: partial_center,
-            ne: partial_e,
-            sw: partial_s,
-            se: partial_se,
-        }));
-
-        combine(Grid2 {
-            nw: final_nw,
-            ne: final_ne,
-            sw: final_sw,
-            se: final_se,
-        })
+        let grandkids: Grid2<Grid2<U>> = self.map(|t| split(ctx, t));
+
+        let [partial_nw, partial_ne, partial_sw, partial_se] = self.to_array().map(|t| f0(ctx, t));
+        let partial_n = {
+            let north = combine(ctx, grandkids.north());
+            f0(ctx, north)
+        };
+        let partial_s = {
+            let south = combine(ctx, grandkids.south());
+            f0(ctx, south)
+        };
+        let partial_e = {
+            let east = combine(ctx, grandkids.east());
+            f0(ctx, east)
+        };
+        let partial_w = {
+            let west = combine(ctx, grandkids.west());
+            f0(ctx, west)
+        };
+        let partial_center = {
+            let center = combine(ctx, grandkids.center());
+            f0(ctx, center)
+        };
+
+        let quads = [
+            Grid2 {
+                nw: partial_nw,
+                ne: partial_n,
+                sw: partial_w,
+                se: partial_center,
+            },
+            Grid2 {
+                nw: partial_n,
+                ne: partial_ne,
+                sw: partial_center,
+                se: partial_e,
+            },
+            Grid2 {
+                nw: partial_w,
+                ne: partial_center,
+                sw: partial_sw,
+                se: partial_s,
+            },
+            Grid2 {
+                nw: partial_center,
+                ne: partial_e,
+                sw: partial_s,
+                se: partial_se,
+            },
+        ];
+        let finals = quads.map(|quad| {
+            let square = combine(ctx, quad);
+            f1(ctx, square)
+        });
+
+        combine(ctx, finals.to_grid())
     }
 }
 
@@ -233,7 +264,13 @@ impl<T, U, V, const N: usize> ArrayUnzipExt<T, U, V, N> for [T; N] {
 }
 
 pub trait BitGrid:
-    Sized + Copy + Eq + BitAnd<Output = Self> + BitOr<Output = Self> + BitXor<Output = Self>
+    Sized
+    + Copy
+    + Eq
+    + BitAnd<Output = Self>
+    + BitOr<Output = Self>
+    + BitXor<Output = Self>
+    + Not<Output = Self>
 {
     const ROWS: usize;
     const COLS: usize;
@@ -249,6 +286,7 @@ where
     Self: BitAnd<Output = Self>
         + BitOr<Output = Self>
         + BitXor<Output = Self>
+        + Not<Output = Self>
         + Shl<Output = Self>
         + Shr<Output = Self>,
     T: SimdElement + num::PrimInt,